@@ -0,0 +1,71 @@
+//! Measures the round-trip time of messages sent with [`ewebsock::ws_connect`].
+//!
+//! First start the echo server with `cargo r -p echo_server`, then run:
+//!
+//! ```sh
+//! cargo bench -p ewebsock --bench latency
+//! ```
+//!
+//! Set `EWEBSOCK_ECHO_URL` to use another echo server.
+//! Without an echo server, this prints a warning and measures nothing.
+
+use std::{
+    ops::ControlFlow,
+    sync::mpsc,
+    time::{Duration, Instant},
+};
+
+use ewebsock::{WsEvent, WsMessage};
+
+const NUM_MESSAGES: usize = 1000;
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+fn main() {
+    let url = std::env::var("EWEBSOCK_ECHO_URL").unwrap_or_else(|_| "ws://127.0.0.1:9001".into());
+
+    let (event_tx, event_rx) = mpsc::channel();
+    let sender = ewebsock::ws_connect(
+        url.clone(),
+        Box::new(move |event| {
+            if event_tx.send(event).is_ok() {
+                ControlFlow::Continue(())
+            } else {
+                ControlFlow::Break(())
+            }
+        }),
+    )
+    .unwrap();
+
+    match event_rx.recv_timeout(TIMEOUT) {
+        Ok(WsEvent::Opened(_)) => {}
+        event => {
+            // `cargo test --all-targets` runs this too, usually without an echo server.
+            eprintln!("Failed to connect to {url} (is the echo server running?): {event:?}");
+            return;
+        }
+    }
+
+    let mut round_trips = Vec::with_capacity(NUM_MESSAGES);
+    for i in 0..NUM_MESSAGES {
+        let message = WsMessage::Text(i.to_string());
+        let start = Instant::now();
+        sender.send(message.clone());
+        loop {
+            match event_rx.recv_timeout(TIMEOUT) {
+                Ok(WsEvent::Message(reply)) if reply == message => break,
+                Ok(WsEvent::Message(_) | WsEvent::RoundTripTime(_)) => {}
+                event => panic!("Expected the echo of message {i}, got: {event:?}"),
+            }
+        }
+        round_trips.push(start.elapsed());
+    }
+
+    round_trips.sort();
+    let mean = round_trips.iter().sum::<Duration>() / NUM_MESSAGES as u32;
+    let percentile = |p: usize| round_trips[(NUM_MESSAGES - 1) * p / 100];
+    println!("Round-trip time of {NUM_MESSAGES} messages to {url}:");
+    println!("  mean: {mean:?}");
+    println!("  p50:  {:?}", percentile(50));
+    println!("  p99:  {:?}", percentile(99));
+    println!("  max:  {:?}", percentile(100));
+}
@@ -0,0 +1,200 @@
+//! An async backend that works with any executor, e.g. `smol`, `async-std` or `futures`.
+//!
+//! Enabled with the `agnostic` feature.
+//!
+//! Instead of spawning the connection itself, this returns it as a [`Connection`] future,
+//! which you spawn on your executor (or `.await`).
+//! The [`WsSender`] and [`crate::WsReceiver`] work just like those at the top level.
+//!
+//! ``` no_run
+//! let (sender, receiver, connection) =
+//!     ewebsock::agnostic::connect("ws://example.com", ewebsock::Options::default());
+//! std::thread::spawn(move || futures_lite::future::block_on(connection));
+//!
+//! sender.send(ewebsock::WsMessage::Text("Hello!".into()));
+//! while let Some(event) = receiver.recv() {
+//!     println!("Received {:?}", event);
+//! }
+//! ```
+
+use std::{
+    future::Future,
+    net::SocketAddr,
+    pin::Pin,
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use async_channel::{Receiver, Sender, TrySendError};
+use async_io::Timer;
+use futures_util::StreamExt as _;
+use tungstenite::handshake::client::{Request, Response};
+
+#[cfg(feature = "tls")]
+use crate::tungstenite_common::{server_name, tls_config};
+use crate::{
+    tungstenite_common::{
+        websocket_config, within, ws_connect_async, AsyncRuntime, BoxFuture, Channel, Deadline,
+        SEND_QUEUE_CAPACITY,
+    },
+    Error, EventHandler, Options, Result, TimeoutKind, TlsOptions, WsMessage, WsReceiver,
+};
+
+pub use crate::tungstenite_common::WsSender;
+
+/// The future that runs a connection.
+///
+/// Spawn it on your executor, or `.await` it.
+/// It finishes once the connection has been closed, and will not be reconnected.
+pub type Connection = Pin<Box<dyn Future<Output = ()> + Send + 'static>>;
+
+impl Channel for Sender<WsMessage> {
+    fn try_send(&self, message: WsMessage) -> Result<()> {
+        Sender::try_send(self, message).map_err(|err| match err {
+            TrySendError::Full(_) => Error::SendQueueFull,
+            TrySendError::Closed(_) => Error::Closed,
+        })
+    }
+}
+
+/// A TCP stream, with or without TLS.
+pub(crate) trait Stream:
+    futures_lite::AsyncRead + futures_lite::AsyncWrite + Send + Unpin
+{
+}
+
+impl<S: futures_lite::AsyncRead + futures_lite::AsyncWrite + Send + Unpin> Stream for S {}
+
+/// Runs the connections on whatever executor polls them, with `async-io` for sockets and timers.
+struct Agnostic;
+
+impl AsyncRuntime for Agnostic {
+    type TcpStream = async_net::TcpStream;
+    type WebSocketStream = async_tungstenite::WebSocketStream<Box<dyn Stream>>;
+    type Sender = Sender<WsMessage>;
+    type Receiver = Receiver<WsMessage>;
+    type Sleep = Timer;
+
+    fn channel() -> (Self::Sender, Self::Receiver) {
+        async_channel::bounded(SEND_QUEUE_CAPACITY)
+    }
+
+    fn poll_recv(rx: &mut Self::Receiver, cx: &mut Context<'_>) -> Poll<Option<WsMessage>> {
+        rx.poll_next_unpin(cx)
+    }
+
+    fn try_recv(rx: &mut Self::Receiver) -> Option<WsMessage> {
+        rx.try_recv().ok()
+    }
+
+    fn sleep_until(at: Instant) -> Self::Sleep {
+        Timer::at(at)
+    }
+
+    fn resolve(host: &str, port: u16) -> BoxFuture<'_, std::io::Result<Vec<SocketAddr>>> {
+        Box::pin(async_net::resolve((host, port)))
+    }
+
+    fn connect(addr: SocketAddr) -> BoxFuture<'static, std::io::Result<Self::TcpStream>> {
+        Box::pin(async_net::TcpStream::connect(addr))
+    }
+
+    fn handshake<'a>(
+        request: Request,
+        stream: Self::TcpStream,
+        host: &'a str,
+        tls: bool,
+        options: &'a Options,
+        open: Option<Instant>,
+    ) -> BoxFuture<'a, Result<(Self::WebSocketStream, Response)>> {
+        Box::pin(async move {
+            let stream = if tls {
+                let deadline = Deadline::step(
+                    open,
+                    options.tls_handshake_timeout,
+                    TimeoutKind::TlsHandshake,
+                );
+                within::<Self, _>(deadline, tls_handshake(stream, host, &options.tls)).await?
+            } else {
+                Box::new(stream)
+            };
+
+            let deadline = Deadline::step(open, options.upgrade_timeout, TimeoutKind::Upgrade);
+            let config = websocket_config(options)?;
+            within::<Self, _>(deadline, async {
+                async_tungstenite::client_async_with_config(request, stream, Some(config))
+                    .await
+                    .map_err(Error::from)
+            })
+            .await
+        })
+    }
+}
+
+/// Establish TLS on top of the TCP `stream`.
+///
+/// This backend always uses rustls, even with the `native-tls` feature.
+#[cfg(feature = "tls")]
+async fn tls_handshake(
+    stream: async_net::TcpStream,
+    host: &str,
+    options: &TlsOptions,
+) -> Result<Box<dyn Stream>> {
+    let connector = futures_rustls::TlsConnector::from(tls_config(options)?);
+    let stream = connector
+        .connect(server_name(host)?, stream)
+        .await
+        .map_err(|err| match err.kind() {
+            // How rustls reports a failed handshake:
+            std::io::ErrorKind::InvalidData => Error::Tls(std::sync::Arc::new(err)),
+            _ => Error::Io(std::sync::Arc::new(err)),
+        })?;
+    Ok(Box::new(stream))
+}
+
+/// Without the `tls` feature, this backend cannot do TLS,
+/// even with the `native-tls` feature.
+#[cfg(not(feature = "tls"))]
+async fn tls_handshake(
+    _stream: async_net::TcpStream,
+    _host: &str,
+    _options: &TlsOptions,
+) -> Result<Box<dyn Stream>> {
+    Err(Error::Url(
+        "`wss://` URLs need the `tls` feature with the agnostic backend".to_owned(),
+    ))
+}
+
+/// Connect to the given URL, and return a sender, a receiver,
+/// and the [`Connection`] future that you need to spawn.
+///
+/// All fields of [`Options`] are supported.
+/// Errors, including a bad URL, are reported as [`crate::WsEvent::Error`]
+/// once the [`Connection`] runs.
+pub fn connect(url: impl Into<String>, options: Options) -> (WsSender, WsReceiver, Connection) {
+    let (receiver, on_event) = WsReceiver::new();
+    let (sender, connection) = ws_connect(url.into(), options, on_event);
+    (sender, receiver, connection)
+}
+
+/// Connect, and call the given event handler on each received event.
+///
+/// Returns the sender, and the [`Connection`] future that you need to spawn.
+///
+/// The connection is closed when the [`WsSender`] is dropped,
+/// or when `on_event` returns [`std::ops::ControlFlow::Break`].
+pub fn ws_connect(url: String, options: Options, on_event: EventHandler) -> (WsSender, Connection) {
+    let (sender, connection) = ws_connect_async::<Agnostic>(url, options, on_event);
+    (sender, Box::pin(connection))
+}
+
+/// Connect, and call the given event handler on each received event.
+///
+/// Returns the [`Connection`] future that you need to spawn.
+///
+/// The connection is closed when `on_event` returns [`std::ops::ControlFlow::Break`].
+pub fn ws_receive(url: String, options: Options, on_event: EventHandler) -> Connection {
+    let (sender, connection) = ws_connect(url, options, on_event);
+    sender.forget();
+    connection
+}
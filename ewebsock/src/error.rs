@@ -0,0 +1,187 @@
+use std::sync::Arc;
+
+/// The underlying error, shared so that [`Error`] (and so [`crate::WsEvent`]) can be cloned.
+type Source = Arc<dyn std::error::Error + Send + Sync>;
+
+/// Something that went wrong with a connection.
+///
+/// The source errors from the underlying WebSocket implementation are kept,
+/// and can be reached with [`std::error::Error::source`].
+#[derive(Clone, Debug)]
+#[non_exhaustive]
+pub enum Error {
+    /// The [`crate::Options`] are invalid, or contain a setting that the backend does not support.
+    Options(String),
+
+    /// The URL is invalid, or is not a WebSocket URL.
+    Url(String),
+
+    /// Failed to resolve the host name.
+    Dns(Arc<std::io::Error>),
+
+    /// Failed to establish a TLS connection.
+    Tls(Source),
+
+    /// The server responded to the handshake with something other than `101 Switching Protocols`.
+    HandshakeRejected {
+        /// The HTTP status code of the response, e.g. `401` or `404`.
+        status: u16,
+    },
+
+    /// The other side violated the WebSocket protocol.
+    Protocol(Source),
+
+    /// A buffer exceeded its size limit.
+    Capacity(Source),
+
+    /// A received message or frame was larger than allowed.
+    ///
+    /// See [`crate::Options::max_message_size`] and [`crate::Options::max_frame_size`].
+    MessageTooLarge {
+        /// The size of the message or frame, in bytes.
+        size: usize,
+
+        /// The largest size allowed, in bytes.
+        max_size: usize,
+    },
+
+    /// An I/O error, e.g. the connection was reset.
+    Io(Arc<std::io::Error>),
+
+    /// The connection has already been closed.
+    Closed,
+
+    /// Something took too long.
+    Timeout(TimeoutKind),
+
+    /// The outbox is full, and its [`crate::OverflowPolicy`] is to reject new messages.
+    OutboxFull,
+
+    /// Too many messages are already waiting to be written to the connection.
+    SendQueueFull,
+
+    /// The close code or reason given to `WsSender::close_with` cannot be sent.
+    InvalidClose(String),
+
+    /// The message given to `WsSender::try_send` cannot be sent,
+    /// e.g. a [`crate::WsMessage::Unknown`], or a ping on web.
+    InvalidMessage(String),
+
+    /// Failed to spawn the thread that handles the connection.
+    Spawn(Arc<std::io::Error>),
+
+    /// An error from the browser `WebSocket` API.
+    ///
+    /// Only on web.
+    Js(String),
+}
+
+/// What took too long, in an [`Error::Timeout`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum TimeoutKind {
+    /// Nothing was received in reply to a heartbeat ping.
+    ///
+    /// See [`crate::Options::heartbeat`].
+    Heartbeat,
+
+    /// The TCP connection to the server was not established in time.
+    ///
+    /// See [`crate::Options::connect_timeout`].
+    Connect,
+
+    /// The TLS handshake of a `wss://` connection did not finish in time.
+    ///
+    /// See [`crate::Options::tls_handshake_timeout`].
+    TlsHandshake,
+
+    /// The server did not accept the WebSocket upgrade request in time.
+    ///
+    /// See [`crate::Options::upgrade_timeout`].
+    Upgrade,
+
+    /// The connection did not open in time.
+    ///
+    /// See [`crate::Options::open_timeout`].
+    Open,
+
+    /// Nothing was received for too long.
+    ///
+    /// See [`crate::Options::idle_timeout`].
+    Idle,
+}
+
+impl std::fmt::Display for TimeoutKind {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Heartbeat => write!(f, "no reply to the heartbeat ping"),
+            Self::Connect => write!(f, "the TCP connection was not established"),
+            Self::TlsHandshake => write!(f, "the TLS handshake did not finish"),
+            Self::Upgrade => write!(f, "the server did not accept the WebSocket upgrade"),
+            Self::Open => write!(f, "the connection did not open"),
+            Self::Idle => write!(f, "nothing was received"),
+        }
+    }
+}
+
+impl Error {
+    /// A [`Self::Protocol`] error with the given description.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub(crate) fn protocol(message: impl Into<String>) -> Self {
+        Self::Protocol(Arc::from(Box::<dyn std::error::Error + Send + Sync>::from(
+            message.into(),
+        )))
+    }
+}
+
+impl std::fmt::Display for Error {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::Options(message) => write!(f, "Invalid options: {message}"),
+            Self::Url(message) => write!(f, "Invalid URL: {message}"),
+            Self::Dns(err) => write!(f, "Failed to resolve host: {err}"),
+            Self::Tls(err) => write!(f, "TLS error: {err}"),
+            Self::HandshakeRejected { status } => {
+                write!(
+                    f,
+                    "The server rejected the handshake with HTTP status {status}"
+                )
+            }
+            Self::Protocol(err) => write!(f, "WebSocket protocol error: {err}"),
+            Self::Capacity(err) => write!(f, "Capacity exceeded: {err}"),
+            Self::MessageTooLarge { size, max_size } => write!(
+                f,
+                "Received a message of {size} bytes, larger than the limit of {max_size} bytes"
+            ),
+            Self::Io(err) => write!(f, "I/O error: {err}"),
+            Self::Closed => write!(f, "The connection is closed"),
+            Self::Timeout(kind) => write!(f, "Timed out: {kind}"),
+            Self::OutboxFull => write!(f, "The outbox of unsent messages is full"),
+            Self::SendQueueFull => write!(f, "Too many messages are waiting to be sent"),
+            Self::InvalidClose(message) => write!(f, "Invalid close frame: {message}"),
+            Self::InvalidMessage(message) => write!(f, "Invalid message: {message}"),
+            Self::Spawn(err) => write!(f, "Failed to spawn thread: {err}"),
+            Self::Js(message) => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Dns(err) | Self::Io(err) | Self::Spawn(err) => Some(err.as_ref()),
+            Self::Tls(err) | Self::Protocol(err) | Self::Capacity(err) => Some(err.as_ref()),
+            Self::Options(_)
+            | Self::Url(_)
+            | Self::HandshakeRejected { .. }
+            | Self::MessageTooLarge { .. }
+            | Self::Closed
+            | Self::Timeout(_)
+            | Self::OutboxFull
+            | Self::SendQueueFull
+            | Self::InvalidClose(_)
+            | Self::InvalidMessage(_)
+            | Self::Js(_) => None,
+        }
+    }
+}
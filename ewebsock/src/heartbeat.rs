@@ -0,0 +1,126 @@
+use std::time::Duration;
+
+use crate::WsMessage;
+
+/// How to detect a dead connection by pinging the server.
+///
+/// See [`crate::Options::heartbeat`].
+///
+/// A ping is sent every `interval`.
+/// If nothing at all is received within `timeout` of a ping,
+/// the connection is considered dead: an [`crate::Error::Timeout`] is reported,
+/// and the connection is closed (and reconnected, if [`crate::Options::reconnect`] is set).
+///
+/// The time until the reply to each ping is reported with a [`crate::WsEvent::RoundTripTime`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct HeartbeatOptions {
+    /// How often to send a ping.
+    pub interval: Duration,
+
+    /// How long to wait for anything to be received after a ping.
+    pub timeout: Duration,
+
+    /// The message to send as a ping on web,
+    /// where the browser does not let us send ping frames.
+    ///
+    /// Must be [`WsMessage::Text`] or [`WsMessage::Binary`].
+    /// The server is expected to reply with [`Self::web_pong`].
+    pub web_ping: WsMessage,
+
+    /// The reply to [`Self::web_ping`] that the round-trip time is measured to on web.
+    ///
+    /// It is still reported as a [`crate::WsEvent::Message`].
+    pub web_pong: WsMessage,
+}
+
+impl Default for HeartbeatOptions {
+    fn default() -> Self {
+        Self {
+            interval: Duration::from_secs(30),
+            timeout: Duration::from_secs(10),
+            web_ping: WsMessage::Text("ping".to_owned()),
+            web_pong: WsMessage::Text("pong".to_owned()),
+        }
+    }
+}
+
+/// What [`Heartbeat::poll`] wants done.
+pub(crate) enum Beat {
+    /// Send a ping now.
+    Ping,
+
+    /// Nothing was received in time - the connection is dead.
+    TimedOut,
+}
+
+/// Keeps track of the pings for one connection.
+///
+/// All times are measured from when the connection was opened.
+pub(crate) struct Heartbeat {
+    options: HeartbeatOptions,
+
+    next_ping: Duration,
+
+    /// When we sent the oldest ping that has not been answered.
+    ping_sent: Option<Duration>,
+
+    last_received: Duration,
+}
+
+impl Heartbeat {
+    pub fn new(options: &HeartbeatOptions) -> Self {
+        Self {
+            options: options.clone(),
+            next_ping: options.interval,
+            ping_sent: None,
+            last_received: Duration::ZERO,
+        }
+    }
+
+    pub fn options(&self) -> &HeartbeatOptions {
+        &self.options
+    }
+
+    /// Call when anything is received.
+    ///
+    /// Returns the round-trip time if it is the reply to our ping.
+    pub fn received(&mut self, now: Duration, is_pong: bool) -> Option<Duration> {
+        self.last_received = now;
+        if is_pong {
+            self.ping_sent.take().map(|sent| now.saturating_sub(sent))
+        } else {
+            None
+        }
+    }
+
+    /// Call at [`Self::next_wakeup`], or whenever convenient.
+    pub fn poll(&mut self, now: Duration) -> Option<Beat> {
+        if let Some(deadline) = self.deadline() {
+            if deadline <= now {
+                return Some(Beat::TimedOut);
+            }
+        }
+        if self.next_ping <= now {
+            if self.deadline().is_none() {
+                self.ping_sent = Some(now);
+            }
+            self.next_ping = now + self.options.interval;
+            return Some(Beat::Ping);
+        }
+        None
+    }
+
+    /// When [`Self::poll`] should be called next.
+    pub fn next_wakeup(&self) -> Duration {
+        match self.deadline() {
+            Some(deadline) => deadline.min(self.next_ping),
+            None => self.next_ping,
+        }
+    }
+
+    /// When the connection is dead, unless something is received before then.
+    fn deadline(&self) -> Option<Duration> {
+        let sent = self.ping_sent?;
+        (self.last_received < sent).then(|| sent + self.options.timeout)
+    }
+}
@@ -2,45 +2,89 @@
 //!
 //! Usage:
 //! ``` no_run
-//! let (mut sender, receiver) = ewebsock::connect("ws://example.com").unwrap();
+//! let (sender, receiver) = ewebsock::connect("ws://example.com").unwrap();
 //! sender.send(ewebsock::WsMessage::Text("Hello!".into()));
 //! while let Some(event) = receiver.try_recv() {
 //!     println!("Received {:?}", event);
 //! }
 //! ```
 //!
+//! In async code, the [`WsReceiver`] is a [`futures_core::Stream`] of [`WsEvent`]s,
+//! and the [`WsSender`] a [`futures_sink::Sink`] of [`WsMessage`]s.
+//!
+//! ## Backends
+//! The top-level functions, like [`connect`], use the browser's `WebSocket` on web,
+//! and the threaded backend in `ewebsock::thread` on native, no matter which features are enabled.
+//!
+//! On native, each backend is also available as a module of its own,
+//! with its own `connect` functions, which all return the same [`WsSender`].
+//! They can all be used in the same build:
+//! * `thread`: one thread per connection. Always available.
+//! * `tokio`: tasks on a tokio runtime. Needs the `tokio` feature.
+//! * `agnostic`: a future to run on any executor. Needs the `agnostic` feature.
+//!
 //! ## Feature flags
 #![doc = document_features::document_features!()]
 //!
 
 #![warn(missing_docs)] // let's keep ewebsock well-documented
 
+use std::{
+    pin::Pin,
+    sync::{Arc, Mutex},
+    task::{Context, Poll, Waker},
+};
+
+mod error;
+mod heartbeat;
+mod options;
+mod outbox;
+mod reconnect;
+mod tls;
+
+pub use error::{Error, TimeoutKind};
+pub use heartbeat::HeartbeatOptions;
+pub use options::Options;
+pub use outbox::{OutboxOptions, OverflowPolicy};
+pub use reconnect::ReconnectOptions;
+pub use tls::{Certificate, ClientIdentity, PrivateKey, TlsOptions};
+
 #[cfg(not(target_arch = "wasm32"))]
-#[cfg(not(feature = "tokio"))]
-mod native_tungstenite;
+mod tungstenite_common;
 
 #[cfg(not(target_arch = "wasm32"))]
-#[cfg(not(feature = "tokio"))]
-pub use native_tungstenite::*;
+#[cfg(feature = "agnostic")]
+pub mod agnostic;
 
 #[cfg(not(target_arch = "wasm32"))]
-#[cfg(feature = "tokio")]
-mod native_tungstenite_tokio;
+pub mod thread;
+
+#[cfg(not(target_arch = "wasm32"))]
+pub use thread::{
+    ws_connect_blocking, ws_connect_blocking_with_options, ws_receiver_blocking,
+    ws_receiver_blocking_with_options,
+};
 
 #[cfg(not(target_arch = "wasm32"))]
 #[cfg(feature = "tokio")]
-pub use native_tungstenite_tokio::*;
+pub mod tokio;
 
 #[cfg(target_arch = "wasm32")]
 mod web;
 
+/// The backend of the top-level functions.
+#[cfg(not(target_arch = "wasm32"))]
+use thread as default_backend;
+
 #[cfg(target_arch = "wasm32")]
-pub use web::*;
+use web as default_backend;
+
+pub use default_backend::WsSender;
 
 // ----------------------------------------------------------------------------
 
 /// A web-socket message.
-#[derive(Clone, Debug)]
+#[derive(Clone, Debug, PartialEq, Eq)]
 pub enum WsMessage {
     /// Binary message.
     Binary(Vec<u8>),
@@ -59,25 +103,195 @@ pub enum WsMessage {
     Pong(Vec<u8>),
 }
 
+impl WsMessage {
+    /// The size of the payload, in bytes.
+    pub(crate) fn num_bytes(&self) -> usize {
+        match self {
+            Self::Binary(data) | Self::Ping(data) | Self::Pong(data) => data.len(),
+            Self::Text(text) | Self::Unknown(text) => text.len(),
+        }
+    }
+}
+
 /// Something happening with the connection.
 #[derive(Clone, Debug)]
 pub enum WsEvent {
     /// The connection has been established, and you can start sending messages.
-    Opened,
+    Opened(ConnectionInfo),
 
     /// A message has been received.
     Message(WsMessage),
 
     /// An error occurred.
-    Error(String),
+    Error(Error),
 
     /// The connection has been closed.
-    Closed,
+    Closed(CloseInfo),
+
+    /// The connection failed or was lost, and we will try to reconnect after the given delay.
+    ///
+    /// Only emitted if [`Options::reconnect`] is set.
+    Reconnecting {
+        /// The number of attempts since we were last connected, starting at 1.
+        attempt: u32,
+
+        /// How long we wait before this attempt.
+        delay: std::time::Duration,
+    },
+
+    /// The connection has been re-established after a [`Self::Reconnecting`].
+    ///
+    /// The first successful connection is always reported as [`Self::Opened`],
+    /// and any later ones as [`Self::Reconnected`].
+    Reconnected(ConnectionInfo),
+
+    /// Some outgoing messages were dropped because the outbox was full.
+    ///
+    /// Reported when the connection opens, just before the buffered messages are sent.
+    /// See [`Options::outbox`].
+    OutgoingDropped {
+        /// The number of dropped messages.
+        count: usize,
+    },
+
+    /// The time it took the server to reply to a heartbeat ping.
+    ///
+    /// Only emitted if [`Options::heartbeat`] is set.
+    RoundTripTime(std::time::Duration),
+}
+
+/// What we know about an established connection.
+///
+/// Reported in [`WsEvent::Opened`].
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ConnectionInfo {
+    /// The HTTP status code of the handshake response, e.g. `101`.
+    ///
+    /// Always `None` on web, where the browser does not expose it.
+    pub status: Option<u16>,
+
+    /// The headers of the handshake response, in the order the server sent them.
+    ///
+    /// Always empty on web, where the browser does not expose them.
+    pub headers: Vec<(String, String)>,
+
+    /// The subprotocol picked by the server, if any.
+    ///
+    /// This is always one of [`Options::subprotocols`].
+    pub protocol: Option<String>,
+
+    /// The extensions negotiated with the server (the `Sec-WebSocket-Extensions` header), if any.
+    pub extensions: Option<String>,
+}
+
+impl ConnectionInfo {
+    /// The value of the first response header with the given name, ignoring case.
+    ///
+    /// Always `None` on web.
+    pub fn header(&self, name: &str) -> Option<&str> {
+        self.headers
+            .iter()
+            .find(|(key, _)| key.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+    }
+}
+
+/// Why and how a connection was closed.
+///
+/// Reported in [`WsEvent::Closed`].
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct CloseInfo {
+    /// The close code.
+    ///
+    /// Some common ones are:
+    /// * `1000`: normal closure
+    /// * `1001`: going away, e.g. the server is shutting down
+    /// * `1005`: the close frame had no code
+    /// * `1006`: the connection was lost without a close frame
+    /// * `1008`: policy violation
+    /// * `4000`–`4999`: application specific
+    ///
+    /// See <https://www.iana.org/assignments/websocket/websocket.xml#close-code-number>.
+    pub code: u16,
+
+    /// The reason given in the close frame, if any.
+    pub reason: String,
+
+    /// `true` if the closing handshake completed,
+    /// `false` if the connection was lost.
+    pub was_clean: bool,
+}
+
+impl CloseInfo {
+    /// The connection was lost without a closing handshake.
+    pub(crate) fn abnormal() -> Self {
+        Self {
+            code: 1006,
+            reason: String::new(),
+            was_clean: false,
+        }
+    }
+}
+
+/// Check that the given close code and reason can be sent in a close frame.
+fn check_close_frame(code: u16, reason: &str) -> Result<()> {
+    if code != 1000 && !(3000..=4999).contains(&code) {
+        return Err(Error::InvalidClose(format!(
+            "Close code {code} cannot be sent. Use 1000 or one in 3000-4999."
+        )));
+    }
+    if 123 < reason.len() {
+        return Err(Error::InvalidClose(format!(
+            "The close reason is {} bytes, but at most 123 bytes are allowed",
+            reason.len()
+        )));
+    }
+    Ok(())
 }
 
 /// Receiver for incoming [`WsEvent`]s.
+///
+/// The events can be received with [`Self::try_recv`] (e.g. once per frame),
+/// by blocking on native, or asynchronously with [`Self::recv_async`] or as a
+/// [`futures_core::Stream`] (on any executor, including `wasm-bindgen-futures` on web).
+///
+/// When this is dropped, the connection is closed (once the next event arrives).
 pub struct WsReceiver {
     rx: std::sync::mpsc::Receiver<WsEvent>,
+
+    /// Woken when an event arrives, or the connection ends.
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+/// Sends the events to a [`WsReceiver`], and wakes up whoever is waiting for them.
+struct EventSender {
+    /// Taken when this is dropped.
+    tx: Option<std::sync::mpsc::Sender<WsEvent>>,
+
+    waker: Arc<Mutex<Option<Waker>>>,
+}
+
+impl EventSender {
+    /// Returns `false` if the [`WsReceiver`] has been dropped.
+    fn send(&self, event: WsEvent) -> bool {
+        let sent = self.tx.as_ref().map_or(false, |tx| tx.send(event).is_ok());
+        self.wake();
+        sent
+    }
+
+    fn wake(&self) {
+        if let Some(waker) = self.waker.lock().ok().and_then(|mut waker| waker.take()) {
+            waker.wake();
+        }
+    }
+}
+
+impl Drop for EventSender {
+    fn drop(&mut self) {
+        // Disconnect before waking, so that the receiver sees that the connection has ended.
+        self.tx = None;
+        self.wake();
+    }
 }
 
 impl WsReceiver {
@@ -91,15 +305,20 @@ impl WsReceiver {
     /// This can be used to wake up the UI thread.
     pub fn new_with_callback(wake_up: impl Fn() + Send + Sync + 'static) -> (Self, EventHandler) {
         let (tx, rx) = std::sync::mpsc::channel();
+        let waker = Arc::new(Mutex::new(None));
+        let sender = EventSender {
+            tx: Some(tx),
+            waker: waker.clone(),
+        };
         let on_event = Box::new(move |event| {
             wake_up(); // wake up UI thread
-            if tx.send(event).is_ok() {
+            if sender.send(event) {
                 std::ops::ControlFlow::Continue(())
             } else {
                 std::ops::ControlFlow::Break(())
             }
         });
-        let ws_receiver = WsReceiver { rx };
+        let ws_receiver = WsReceiver { rx, waker };
         (ws_receiver, on_event)
     }
 
@@ -107,10 +326,107 @@ impl WsReceiver {
     pub fn try_recv(&self) -> Option<WsEvent> {
         self.rx.try_recv().ok()
     }
+
+    /// Wait for the next event without blocking the thread.
+    ///
+    /// Returns `None` once the connection has ended, and all its events have been received.
+    ///
+    /// This works with any executor, and does not need the `tokio` feature.
+    pub async fn recv_async(&self) -> Option<WsEvent> {
+        std::future::poll_fn(|cx| self.poll_recv(cx)).await
+    }
+
+    fn poll_recv(&self, cx: &mut Context<'_>) -> Poll<Option<WsEvent>> {
+        use std::sync::mpsc::TryRecvError;
+
+        match self.rx.try_recv() {
+            Ok(event) => return Poll::Ready(Some(event)),
+            Err(TryRecvError::Disconnected) => return Poll::Ready(None),
+            Err(TryRecvError::Empty) => {}
+        }
+
+        if let Ok(mut waker) = self.waker.lock() {
+            *waker = Some(cx.waker().clone());
+        }
+
+        // Something may have arrived before the waker was registered:
+        match self.rx.try_recv() {
+            Ok(event) => Poll::Ready(Some(event)),
+            Err(TryRecvError::Disconnected) => Poll::Ready(None),
+            Err(TryRecvError::Empty) => Poll::Pending,
+        }
+    }
+
+    /// Iterate over the events that have already arrived, without blocking.
+    pub fn try_iter(&self) -> std::sync::mpsc::TryIter<'_, WsEvent> {
+        self.rx.try_iter()
+    }
+
+    /// Block until the next event arrives.
+    ///
+    /// Returns `None` once the connection has ended, and all its events have been received.
+    ///
+    /// Not available on web, where blocking the main thread is not allowed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn recv(&self) -> Option<WsEvent> {
+        self.rx.recv().ok()
+    }
+
+    /// Block until the next event arrives, or the timeout elapses.
+    ///
+    /// Fails with [`std::sync::mpsc::RecvTimeoutError::Disconnected`] once the connection
+    /// has ended, and all its events have been received.
+    ///
+    /// Not available on web, where blocking the main thread is not allowed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn recv_timeout(
+        &self,
+        timeout: std::time::Duration,
+    ) -> std::result::Result<WsEvent, std::sync::mpsc::RecvTimeoutError> {
+        self.rx.recv_timeout(timeout)
+    }
+
+    /// Iterate over the events, blocking until each one arrives.
+    ///
+    /// The iteration ends once the connection has ended, and all its events have been received.
+    ///
+    /// Not available on web, where blocking the main thread is not allowed.
+    #[cfg(not(target_arch = "wasm32"))]
+    pub fn iter(&self) -> std::sync::mpsc::Iter<'_, WsEvent> {
+        self.rx.iter()
+    }
+}
+
+/// Ends once the connection has ended, and all its events have been received.
+impl futures_core::Stream for WsReceiver {
+    type Item = WsEvent;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<WsEvent>> {
+        self.poll_recv(cx)
+    }
+}
+
+/// Blocks until each event arrives. See [`WsReceiver::iter`].
+#[cfg(not(target_arch = "wasm32"))]
+impl<'a> IntoIterator for &'a WsReceiver {
+    type Item = WsEvent;
+    type IntoIter = std::sync::mpsc::Iter<'a, WsEvent>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
 }
 
-/// An error.
-pub type Error = String;
+/// Blocks until each event arrives. See [`WsReceiver::iter`].
+#[cfg(not(target_arch = "wasm32"))]
+impl IntoIterator for WsReceiver {
+    type Item = WsEvent;
+    type IntoIter = std::sync::mpsc::IntoIter<WsEvent>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.rx.into_iter()
+    }
+}
 
 /// Short for `Result<T, ewebsock::Error>`.
 pub type Result<T> = std::result::Result<T, Error>;
@@ -128,8 +444,21 @@ pub(crate) type EventHandler = Box<dyn Send + Fn(WsEvent) -> std::ops::ControlFl
 /// See also the [`connect_with_wakeup`] function,
 /// and the more advanced [`ws_connect`].
 pub fn connect(url: impl Into<String>) -> Result<(WsSender, WsReceiver)> {
+    connect_with_options(url, Options::default())
+}
+
+/// Like [`connect`], but with the given [`Options`].
+///
+/// # Errors
+/// * The options contain a setting that is not supported by the backend.
+/// * On native: failure to spawn a thread.
+/// * On web: failure to use `WebSocket` API.
+pub fn connect_with_options(
+    url: impl Into<String>,
+    options: Options,
+) -> Result<(WsSender, WsReceiver)> {
     let (ws_receiver, on_event) = WsReceiver::new();
-    let ws_sender = ws_connect(url.into(), on_event)?;
+    let ws_sender = ws_connect_with_options(url.into(), options, on_event)?;
     Ok((ws_sender, ws_receiver))
 }
 
@@ -143,25 +472,55 @@ pub fn connect(url: impl Into<String>) -> Result<(WsSender, WsReceiver)> {
 /// * On native: failure to spawn a thread.
 /// * On web: failure to use `WebSocket` API.
 ///
-/// Note that you have to wait for [`WsEvent::Opened`] before sending messages.
+/// Messages sent before [`WsEvent::Opened`] are buffered, see [`Options::outbox`].
 pub fn connect_with_wakeup(
     url: impl Into<String>,
     wake_up: impl Fn() + Send + Sync + 'static,
+) -> Result<(WsSender, WsReceiver)> {
+    connect_with_wakeup_and_options(url, Options::default(), wake_up)
+}
+
+/// Like [`connect_with_wakeup`], but with the given [`Options`].
+///
+/// # Errors
+/// * The options contain a setting that is not supported by the backend.
+/// * On native: failure to spawn a thread.
+/// * On web: failure to use `WebSocket` API.
+pub fn connect_with_wakeup_and_options(
+    url: impl Into<String>,
+    options: Options,
+    wake_up: impl Fn() + Send + Sync + 'static,
 ) -> Result<(WsSender, WsReceiver)> {
     let (receiver, on_event) = WsReceiver::new_with_callback(wake_up);
-    let sender = ws_connect(url.into(), on_event)?;
+    let sender = ws_connect_with_options(url.into(), options, on_event)?;
     Ok((sender, receiver))
 }
 
 /// Connect and call the given event handler on each received event.
 ///
+/// The connection is closed when the event handler returns [`std::ops::ControlFlow::Break`].
+///
 /// See [`crate::connect`] for a more high-level version.
 ///
 /// # Errors
 /// * On native: failure to spawn a thread.
 /// * On web: failure to use `WebSocket` API.
 pub fn ws_connect(url: String, on_event: EventHandler) -> Result<WsSender> {
-    ws_connect_impl(url, on_event)
+    ws_connect_with_options(url, Options::default(), on_event)
+}
+
+/// Like [`ws_connect`], but with the given [`Options`].
+///
+/// # Errors
+/// * The options contain a setting that is not supported by the backend.
+/// * On native: failure to spawn a thread.
+/// * On web: failure to use `WebSocket` API.
+pub fn ws_connect_with_options(
+    url: String,
+    options: Options,
+    on_event: EventHandler,
+) -> Result<WsSender> {
+    default_backend::ws_connect(url, options, on_event)
 }
 
 /// Connect and call the given event handler on each received event.
@@ -171,9 +530,25 @@ pub fn ws_connect(url: String, on_event: EventHandler) -> Result<WsSender> {
 ///
 /// This can be slightly more efficent when you don't need to send messages.
 ///
+/// The connection is closed when the event handler returns [`std::ops::ControlFlow::Break`].
+///
 /// # Errors
 /// * On native: failure to spawn receiver thread.
 /// * On web: failure to use `WebSocket` API.
 pub fn ws_receive(url: String, on_event: EventHandler) -> Result<()> {
-    ws_receive_impl(url, on_event)
+    ws_receive_with_options(url, Options::default(), on_event)
+}
+
+/// Like [`ws_receive`], but with the given [`Options`].
+///
+/// # Errors
+/// * The options contain a setting that is not supported by the backend.
+/// * On native: failure to spawn receiver thread.
+/// * On web: failure to use `WebSocket` API.
+pub fn ws_receive_with_options(
+    url: String,
+    options: Options,
+    on_event: EventHandler,
+) -> Result<()> {
+    default_backend::ws_receive(url, options, on_event)
 }
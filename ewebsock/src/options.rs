@@ -0,0 +1,146 @@
+use std::time::Duration;
+
+use crate::{HeartbeatOptions, OutboxOptions, ReconnectOptions, TlsOptions};
+
+/// Options for a connection.
+///
+/// Not every backend supports every option.
+/// Each field documents where it is supported.
+/// Setting an option to a non-default value on a backend that does not support it
+/// results in an error from the connect function, rather than the option being silently ignored.
+///
+/// ```
+/// let options = ewebsock::Options {
+///     max_message_size: Some(1024 * 1024),
+///     ..Default::default()
+/// };
+/// ```
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Options {
+    /// The maximum size of an incoming message, in bytes.
+    ///
+    /// `None` means the backend default: 64 MiB on native, and no limit on web.
+    /// A larger message fails the connection with [`crate::Error::MessageTooLarge`].
+    ///
+    /// Supported on all backends.
+    pub max_message_size: Option<usize>,
+
+    /// The maximum size of an incoming frame, in bytes.
+    ///
+    /// `None` means the backend default (16 MiB on native).
+    /// A larger frame fails the connection with [`crate::Error::MessageTooLarge`].
+    ///
+    /// Supported on native. Not supported on web, where the browser handles the frames.
+    pub max_frame_size: Option<usize>,
+
+    /// How many bytes of outgoing messages to buffer before writing them to the socket.
+    ///
+    /// `None` means the backend default (128 KiB on native).
+    ///
+    /// Supported on native. Not supported on web.
+    pub write_buffer_size: Option<usize>,
+
+    /// The maximum number of bytes of outgoing messages that may be buffered,
+    /// waiting to be written to the socket.
+    /// It must be larger than [`Self::write_buffer_size`].
+    ///
+    /// `None` means no limit.
+    /// Going over it fails the connection with [`crate::Error::Capacity`].
+    ///
+    /// Supported on native. Not supported on web.
+    pub max_write_buffer_size: Option<usize>,
+
+    /// Extra HTTP headers to send with the handshake request,
+    /// e.g. `("Authorization", "Bearer …")` or `("Cookie", "session=…")`.
+    ///
+    /// Headers with the same name are all sent.
+    ///
+    /// Supported on native. Not supported on web,
+    /// where the browser decides what headers to send.
+    pub additional_headers: Vec<(String, String)>,
+
+    /// Subprotocols to offer the server, in order of preference,
+    /// e.g. `graphql-transport-ws`, `mqtt` or `v12.stomp`.
+    ///
+    /// The one picked by the server is reported in [`crate::WsEvent::Opened`].
+    /// The connection fails if the server picks one that was not offered.
+    ///
+    /// See <https://www.iana.org/assignments/websocket/websocket.xml#subprotocol-name>.
+    ///
+    /// Supported on all backends.
+    pub subprotocols: Vec<String>,
+
+    /// Root certificates, client certificates and the like for `wss://` connections.
+    ///
+    /// Supported on native. Not supported on web.
+    pub tls: TlsOptions,
+
+    /// If set, reconnect whenever the connection fails or is lost,
+    /// until the [`crate::WsSender`] is closed or we run out of attempts.
+    ///
+    /// The same [`crate::WsSender`] and [`crate::WsReceiver`] keep working across reconnections.
+    /// Each attempt is announced with a [`crate::WsEvent::Reconnecting`],
+    /// and a successful one with a [`crate::WsEvent::Reconnected`].
+    ///
+    /// Supported on all backends.
+    pub reconnect: Option<ReconnectOptions>,
+
+    /// How to buffer messages sent before the connection is open, or while reconnecting.
+    ///
+    /// Supported on all backends.
+    pub outbox: OutboxOptions,
+
+    /// If set, ping the server regularly, and close the connection if it stops responding.
+    ///
+    /// Supported on all backends.
+    /// On web, where ping frames are not available,
+    /// the application-level [`HeartbeatOptions::web_ping`] message is sent instead.
+    pub heartbeat: Option<HeartbeatOptions>,
+
+    /// If set, close the connection when nothing at all has been received for this long,
+    /// and report it as an [`crate::Error::Timeout`].
+    ///
+    /// Unlike [`Self::heartbeat`], this does not send anything,
+    /// so it also catches servers that answer pings (or TCP keepalives) but have stopped sending data.
+    ///
+    /// Supported on all backends.
+    /// On web, where ping and pong frames are not visible, only messages count.
+    pub idle_timeout: Option<Duration>,
+
+    /// How long to wait for the TCP connection to the server to be established.
+    ///
+    /// `None` means no limit, other than that of the operating system.
+    /// Like the other timeouts, this applies to each attempt to connect,
+    /// and failing it is reported as an [`crate::Error::Timeout`].
+    ///
+    /// Supported on native. Not supported on web, where the browser opens the connection.
+    /// Use [`Self::open_timeout`] there.
+    pub connect_timeout: Option<Duration>,
+
+    /// How long to wait for the TLS handshake of a `wss://` connection,
+    /// once the TCP connection has been established.
+    ///
+    /// `None` means no limit.
+    ///
+    /// Supported on native. Not supported on web.
+    /// The tokio backend does the TLS handshake and the upgrade in one go,
+    /// so there this and [`Self::upgrade_timeout`] add up to one limit for both.
+    pub tls_handshake_timeout: Option<Duration>,
+
+    /// How long to wait for the server to accept the WebSocket upgrade request,
+    /// once the TCP connection (and TLS) has been established.
+    ///
+    /// `None` means no limit.
+    ///
+    /// Supported on native. Not supported on web.
+    pub upgrade_timeout: Option<Duration>,
+
+    /// How long to wait for the connection to open, from start to finish.
+    ///
+    /// `None` means no limit.
+    ///
+    /// Supported on all backends.
+    /// On the threaded backend, resolving the host name counts towards this,
+    /// but is not interrupted by it.
+    pub open_timeout: Option<Duration>,
+}
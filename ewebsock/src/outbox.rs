@@ -0,0 +1,163 @@
+use std::collections::VecDeque;
+
+use crate::{Error, Options, Result, WsMessage};
+
+/// What to do with a message sent while the outbox is full.
+///
+/// See [`OutboxOptions`].
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Drop the oldest buffered message to make room for the new one.
+    #[default]
+    DropOldest,
+
+    /// Drop the new message.
+    DropNewest,
+
+    /// Reject the new message with [`Error::OutboxFull`].
+    Error,
+}
+
+/// How to buffer messages sent before the connection is open, or while reconnecting.
+///
+/// See [`Options::outbox`].
+///
+/// The buffered messages are sent in order once the connection opens.
+/// The number of dropped messages (if any) is reported with a
+/// [`crate::WsEvent::OutgoingDropped`] just before that.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct OutboxOptions {
+    /// The maximum number of messages to buffer.
+    ///
+    /// Messages that were sent while the connection was open, but not yet written when it was lost,
+    /// are buffered for the next connection even beyond this, so that they are never dropped.
+    pub capacity: usize,
+
+    /// What to do with a message sent while the outbox is full.
+    pub overflow: OverflowPolicy,
+}
+
+impl Default for OutboxOptions {
+    fn default() -> Self {
+        Self {
+            capacity: 1000,
+            overflow: OverflowPolicy::default(),
+        }
+    }
+}
+
+/// Holds the messages sent while the connection is not open.
+pub(crate) struct Outbox {
+    options: OutboxOptions,
+
+    /// Is the connection open, so that messages can be sent right away?
+    open: bool,
+
+    /// Has the connection ended for good, so that nothing more can be sent?
+    ended: bool,
+
+    queue: VecDeque<WsMessage>,
+
+    /// Total size of the messages in the `queue`.
+    num_bytes: usize,
+
+    /// Number of messages dropped since the connection was last opened.
+    dropped: usize,
+}
+
+impl Outbox {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            options: options.outbox.clone(),
+            open: false,
+            ended: false,
+            queue: VecDeque::new(),
+            num_bytes: 0,
+            dropped: 0,
+        }
+    }
+
+    /// Can messages be sent right away, instead of being buffered?
+    pub fn is_open(&self) -> bool {
+        self.open
+    }
+
+    /// Has the connection ended for good, so that nothing more can be sent?
+    pub fn is_ended(&self) -> bool {
+        self.ended
+    }
+
+    /// Total size of the buffered messages.
+    pub fn num_bytes(&self) -> usize {
+        self.num_bytes
+    }
+
+    /// Buffer a message until the connection opens.
+    pub fn push(&mut self, message: WsMessage) -> Result<()> {
+        if self.ended {
+            return Err(Error::Closed);
+        }
+
+        if self.queue.len() < self.options.capacity {
+            self.num_bytes += message.num_bytes();
+            self.queue.push_back(message);
+            return Ok(());
+        }
+
+        match self.options.overflow {
+            OverflowPolicy::DropOldest => {
+                if let Some(oldest) = self.queue.pop_front() {
+                    self.num_bytes -= oldest.num_bytes();
+                    self.num_bytes += message.num_bytes();
+                    self.queue.push_back(message);
+                }
+                self.dropped += 1;
+                Ok(())
+            }
+            OverflowPolicy::DropNewest => {
+                self.dropped += 1;
+                Ok(())
+            }
+            OverflowPolicy::Error => Err(Error::OutboxFull),
+        }
+    }
+
+    /// Buffer a message that was already accepted, but could not be written before the connection
+    /// was lost.
+    ///
+    /// Unlike [`Self::push`], this ignores the capacity,
+    /// since the sender has been told that the message was sent.
+    pub fn requeue(&mut self, message: WsMessage) {
+        if !self.ended {
+            self.num_bytes += message.num_bytes();
+            self.queue.push_back(message);
+        }
+    }
+
+    /// Call when the connection opens.
+    ///
+    /// Returns the number of dropped messages, and the buffered messages to send (oldest first).
+    pub fn open(&mut self) -> (usize, VecDeque<WsMessage>) {
+        self.open = true;
+        self.num_bytes = 0;
+        (
+            std::mem::take(&mut self.dropped),
+            std::mem::take(&mut self.queue),
+        )
+    }
+
+    /// Call when the connection fails or is lost.
+    pub fn close(&mut self) {
+        self.open = false;
+    }
+
+    /// Call when the connection has ended for good.
+    ///
+    /// From then on, nothing can be sent.
+    pub fn end(&mut self) {
+        self.open = false;
+        self.ended = true;
+        self.queue.clear();
+        self.num_bytes = 0;
+    }
+}
@@ -0,0 +1,131 @@
+use std::time::Duration;
+
+use crate::{ConnectionInfo, Options, WsEvent};
+
+/// How to reconnect when a connection is lost.
+///
+/// See [`Options::reconnect`].
+///
+/// The delay before attempt `n` is `initial_delay * multiplier^(n-1)`, capped at `max_delay`,
+/// and then randomly adjusted by up to `±jitter` of itself (but never beyond `max_delay`),
+/// so that many clients do not all reconnect at the same time.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ReconnectOptions {
+    /// How long to wait before the first attempt.
+    pub initial_delay: Duration,
+
+    /// The longest we will ever wait between two attempts.
+    pub max_delay: Duration,
+
+    /// How much longer to wait after each failed attempt.
+    pub multiplier: f64,
+
+    /// How much to randomly adjust each delay by, as a fraction of it (0.0–1.0).
+    pub jitter: f64,
+
+    /// Give up after this many attempts in a row have failed.
+    ///
+    /// `None` means never give up.
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for ReconnectOptions {
+    fn default() -> Self {
+        Self {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            multiplier: 2.0,
+            jitter: 0.2,
+            max_attempts: None,
+        }
+    }
+}
+
+impl ReconnectOptions {
+    /// How long to wait before the given attempt (starting at 1).
+    fn delay(&self, attempt: u32) -> Duration {
+        let exponent = attempt.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let delay = self.initial_delay.as_secs_f64() * self.multiplier.powi(exponent);
+        let delay = delay.min(self.max_delay.as_secs_f64());
+        let jitter = self.jitter.clamp(0.0, 1.0) * (2.0 * random_unit() - 1.0);
+        let delay = delay * (1.0 + jitter);
+        // Checked before converting, which panics on delays that do not fit in a `Duration`:
+        if self.max_delay.as_secs_f64() <= delay {
+            self.max_delay
+        } else {
+            Duration::from_secs_f64(delay.max(0.0))
+        }
+    }
+}
+
+/// A random number in `[0, 1)`.
+#[cfg(not(target_arch = "wasm32"))]
+fn random_unit() -> f64 {
+    use std::hash::{BuildHasher as _, Hasher as _};
+
+    // Each `RandomState` is seeded differently, which is all the randomness we need.
+    let random = std::collections::hash_map::RandomState::new()
+        .build_hasher()
+        .finish();
+    (random >> 11) as f64 / (1_u64 << 53) as f64
+}
+
+/// A random number in `[0, 1)`.
+#[cfg(target_arch = "wasm32")]
+fn random_unit() -> f64 {
+    js_sys::Math::random()
+}
+
+/// Keeps track of reconnection attempts for one [`crate::WsSender`].
+pub(crate) struct Backoff {
+    options: Option<ReconnectOptions>,
+
+    /// Number of attempts since the last successful connection.
+    attempt: u32,
+
+    /// Have we ever been connected?
+    has_opened: bool,
+}
+
+impl Backoff {
+    pub fn new(options: &Options) -> Self {
+        Self {
+            options: options.reconnect.clone(),
+            attempt: 0,
+            has_opened: false,
+        }
+    }
+
+    /// Call when a connection has been established, and emit the returned event.
+    ///
+    /// This is [`WsEvent::Opened`] the first time, and [`WsEvent::Reconnected`] after that.
+    pub fn opened(&mut self, info: ConnectionInfo) -> WsEvent {
+        self.attempt = 0;
+        if std::mem::replace(&mut self.has_opened, true) {
+            WsEvent::Reconnected(info)
+        } else {
+            WsEvent::Opened(info)
+        }
+    }
+
+    /// Call when a connection failed or was lost (but not when we closed it ourselves).
+    ///
+    /// Returns the [`WsEvent::Reconnecting`] to emit, and how long to wait before reconnecting,
+    /// or `None` if we should give up.
+    pub fn next_attempt(&mut self) -> Option<(WsEvent, Duration)> {
+        let options = self.options.as_ref()?;
+        self.attempt += 1;
+        if let Some(max_attempts) = options.max_attempts {
+            if max_attempts < self.attempt {
+                log::debug!("Giving up reconnecting after {max_attempts} attempts");
+                return None;
+            }
+        }
+        let delay = options.delay(self.attempt);
+        let event = WsEvent::Reconnecting {
+            attempt: self.attempt,
+            delay,
+        };
+        Some((event, delay))
+    }
+}
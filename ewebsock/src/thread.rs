@@ -0,0 +1,754 @@
+//! The threaded backend, built on [`tungstenite`].
+//!
+//! Each connection runs on a thread of its own, so this works without any async runtime.
+//! This is what the top-level functions, like [`crate::connect`], use on native.
+
+#![allow(deprecated)] // TODO(emilk): Remove when we update tungstenite
+
+use std::{
+    net::{TcpStream, ToSocketAddrs as _},
+    ops::ControlFlow,
+    sync::{
+        mpsc::{Receiver, RecvTimeoutError, SyncSender, TryRecvError, TrySendError},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use polling::{Event, Poller};
+use tungstenite::stream::MaybeTlsStream;
+
+#[cfg(feature = "native-tls")]
+use crate::tungstenite_common::native_tls_connector;
+#[cfg(all(feature = "tls", not(feature = "native-tls")))]
+use crate::tungstenite_common::{server_name, tls_config};
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+use crate::TlsOptions;
+use crate::{
+    tungstenite_common::{
+        connection_info, host_and_port, into_requester, into_tungstenite_message, is_tls,
+        normal_close_frame, websocket_config, Channel, Deadline, Ended, Events, Liveness, Shared,
+        CLOSE_TIMEOUT, SEND_QUEUE_CAPACITY,
+    },
+    ConnectionInfo, Error, EventHandler, Options, Result, TimeoutKind, WsEvent, WsMessage,
+    WsReceiver,
+};
+
+pub use crate::tungstenite_common::WsSender;
+
+type Socket = tungstenite::WebSocket<MaybeTlsStream<TcpStream>>;
+
+/// The key of the socket in the [`Poller`].
+const SOCKET_KEY: usize = 0;
+
+/// The channel to the connection thread, which wakes it up when there is something to do.
+struct Notifying {
+    /// Taken when this is dropped.
+    tx: Option<SyncSender<WsMessage>>,
+
+    poller: Arc<Poller>,
+}
+
+impl Channel for Notifying {
+    fn try_send(&self, message: WsMessage) -> Result<()> {
+        let Some(tx) = &self.tx else {
+            return Err(Error::Closed);
+        };
+        tx.try_send(message).map_err(|err| match err {
+            TrySendError::Full(_) => Error::SendQueueFull,
+            TrySendError::Disconnected(_) => Error::Closed,
+        })?;
+        self.poller.notify().ok();
+        Ok(())
+    }
+}
+
+impl Drop for Notifying {
+    fn drop(&mut self) {
+        // Disconnect before waking, so that the connection thread sees that it should close.
+        self.tx = None;
+        self.poller.notify().ok();
+    }
+}
+
+/// The receiving end of a [`channel`]: the messages for [`ws_connect_blocking`] to send.
+pub struct Outgoing {
+    rx: Receiver<WsMessage>,
+
+    shared: Arc<Shared>,
+
+    /// Notified by the [`WsSender`] when there is something to send, or when it is closed.
+    poller: Arc<Poller>,
+}
+
+/// Create a [`WsSender`], and the [`Outgoing`] end to pass to [`ws_connect_blocking`].
+///
+/// Sending a message wakes up the connection right away.
+/// Messages sent before the connection opens are buffered as configured by [`Options::outbox`],
+/// so pass the same `options` to [`ws_connect_blocking_with_options`].
+///
+/// # Errors
+/// * Failure to create the poller that wakes up the connection.
+pub fn channel(options: &Options) -> Result<(WsSender, Outgoing)> {
+    let (tx, rx) = std::sync::mpsc::sync_channel(SEND_QUEUE_CAPACITY);
+    let shared = Shared::new(options);
+    let poller = Arc::new(Poller::new().map_err(|err| Error::Io(Arc::new(err)))?);
+    let tx = Notifying {
+        tx: Some(tx),
+        poller: poller.clone(),
+    };
+    let sender = WsSender::new(tx, shared.clone());
+    Ok((sender, Outgoing { rx, shared, poller }))
+}
+
+/// Connect to the given URL on a new thread, and return a sender and receiver.
+///
+/// All fields of [`Options`] are supported.
+///
+/// # Errors
+/// * Failure to spawn a thread.
+pub fn connect(url: impl Into<String>, options: Options) -> Result<(WsSender, WsReceiver)> {
+    let (receiver, on_event) = WsReceiver::new();
+    let sender = ws_connect(url.into(), options, on_event)?;
+    Ok((sender, receiver))
+}
+
+/// Connect on a new thread, and call the given event handler on each received event.
+///
+/// Like [`ws_connect`], but it doesn't return a [`WsSender`],
+/// so it can only receive messages, not send them.
+///
+/// The connection is closed when `on_event` returns [`std::ops::ControlFlow::Break`].
+///
+/// # Errors
+/// * Failure to spawn a thread.
+pub fn ws_receive(url: String, options: Options, on_event: EventHandler) -> Result<()> {
+    std::thread::Builder::new()
+        .name("ewebsock".to_owned())
+        .spawn(move || {
+            if let Err(err) = ws_receiver_blocking_with_options(&url, &options, &on_event) {
+                log::error!("WebSocket error: {err}. Connection closed.");
+            } else {
+                log::debug!("WebSocket connection closed.");
+            }
+        })
+        .map_err(|err| Error::Spawn(Arc::new(err)))?;
+
+    Ok(())
+}
+
+/// Connect, and return the socket together with what we know about the connection.
+fn connect_socket(url: &str, options: &Options) -> Result<(Socket, ConnectionInfo)> {
+    let open = options.open_timeout.map(|timeout| Instant::now() + timeout);
+    let request = into_requester(url, options)?;
+    let (host, port) = host_and_port(&request)?;
+    let tls = is_tls(&request)?;
+
+    let addrs = (host.as_str(), port)
+        .to_socket_addrs()
+        .map_err(|err| Error::Dns(Arc::new(err)))?;
+    let deadline = Deadline::step(open, options.connect_timeout, TimeoutKind::Connect);
+    let stream = connect_to_some(addrs, &host, deadline)?;
+
+    // Shares the socket with `stream`, so that we can limit how long each read and write
+    // of the handshakes may block, even once `stream` has been wrapped:
+    let timeouts = stream.try_clone().map_err(|err| Error::Io(Arc::new(err)))?;
+
+    let stream = if tls {
+        let deadline = Deadline::step(
+            open,
+            options.tls_handshake_timeout,
+            TimeoutKind::TlsHandshake,
+        );
+        tls_handshake(stream, &host, &options.tls, &timeouts, deadline)?
+    } else {
+        MaybeTlsStream::Plain(stream)
+    };
+
+    let deadline = Deadline::step(open, options.upgrade_timeout, TimeoutKind::Upgrade);
+    let (mut socket, response) = upgrade(request, stream, options, &timeouts, deadline)?;
+    set_timeouts(&timeouts, None)?;
+
+    match connection_info(options, &response) {
+        Ok(info) => Ok((socket, info)),
+        Err(err) => {
+            socket.close(None).ok();
+            socket.write_pending().ok();
+            Err(err)
+        }
+    }
+}
+
+/// Open a TCP connection to the first of the given addresses that accepts one.
+fn connect_to_some(
+    addrs: impl Iterator<Item = std::net::SocketAddr>,
+    host: &str,
+    deadline: Option<Deadline>,
+) -> Result<TcpStream> {
+    let mut last_err = None;
+    for addr in addrs {
+        let result = match deadline {
+            Some(deadline) => TcpStream::connect_timeout(&addr, deadline.time_left()?),
+            None => TcpStream::connect(addr),
+        };
+        match result {
+            Ok(stream) => return Ok(stream),
+            Err(err) => {
+                log::debug!("Failed to connect to {addr}: {err}");
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(match (last_err, deadline) {
+        (Some(err), Some(deadline)) if is_timeout(&err) => Error::Timeout(deadline.kind),
+        (Some(err), _) => Error::Io(Arc::new(err)),
+        (None, _) => Error::Dns(Arc::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No addresses found for {host:?}"),
+        ))),
+    })
+}
+
+/// Limit how long each read and write on the socket may block to the time left until the deadline.
+fn set_timeouts(socket: &TcpStream, deadline: Option<Deadline>) -> Result<()> {
+    let timeout = deadline.map(|deadline| deadline.time_left()).transpose()?;
+    socket
+        .set_read_timeout(timeout)
+        .and_then(|()| socket.set_write_timeout(timeout))
+        .map_err(|err| Error::Io(Arc::new(err)))
+}
+
+/// Did a read or write fail because of a timeout set with [`set_timeouts`]?
+fn is_timeout(err: &std::io::Error) -> bool {
+    matches!(
+        err.kind(),
+        std::io::ErrorKind::WouldBlock | std::io::ErrorKind::TimedOut
+    )
+}
+
+/// Establish TLS on top of the TCP `stream`.
+///
+/// `timeouts` is a clone of `stream`, used to enforce the `deadline`.
+#[cfg(all(feature = "tls", not(feature = "native-tls")))]
+fn tls_handshake(
+    mut stream: TcpStream,
+    host: &str,
+    options: &TlsOptions,
+    timeouts: &TcpStream,
+    deadline: Option<Deadline>,
+) -> Result<MaybeTlsStream<TcpStream>> {
+    let mut connection = rustls::ClientConnection::new(tls_config(options)?, server_name(host)?)
+        .map_err(|err| Error::Tls(Arc::new(err)))?;
+    while connection.is_handshaking() {
+        set_timeouts(timeouts, deadline)?;
+        if let Err(err) = connection.complete_io(&mut stream) {
+            return Err(match deadline {
+                Some(deadline) if is_timeout(&err) => Error::Timeout(deadline.kind),
+                // How rustls reports a failed handshake:
+                _ if err.kind() == std::io::ErrorKind::InvalidData => Error::Tls(Arc::new(err)),
+                _ => Error::Io(Arc::new(err)),
+            });
+        }
+    }
+    Ok(MaybeTlsStream::Rustls(rustls::StreamOwned::new(
+        connection, stream,
+    )))
+}
+
+/// Establish TLS on top of the TCP `stream`.
+///
+/// `timeouts` is a clone of `stream`, used to enforce the `deadline`.
+#[cfg(feature = "native-tls")]
+fn tls_handshake(
+    stream: TcpStream,
+    host: &str,
+    options: &TlsOptions,
+    timeouts: &TcpStream,
+    deadline: Option<Deadline>,
+) -> Result<MaybeTlsStream<TcpStream>> {
+    let connector = native_tls_connector(options)?;
+    set_timeouts(timeouts, deadline)?;
+    let mut result = connector.connect(host, stream);
+    loop {
+        match result {
+            Ok(stream) => return Ok(MaybeTlsStream::NativeTls(stream)),
+            Err(native_tls::HandshakeError::WouldBlock(handshake)) => {
+                // A read or write timed out. Go on if there is time left:
+                let Some(deadline) = deadline else {
+                    return Err(Error::Io(Arc::new(std::io::ErrorKind::WouldBlock.into())));
+                };
+                set_timeouts(timeouts, Some(deadline))?;
+                result = handshake.handshake();
+            }
+            Err(native_tls::HandshakeError::Failure(err)) => {
+                return Err(Error::Tls(Arc::new(err)));
+            }
+        }
+    }
+}
+
+/// Without the `tls` or `native-tls` feature, [`is_tls`] fails before we get here.
+#[cfg(not(any(feature = "tls", feature = "native-tls")))]
+fn tls_handshake(
+    stream: TcpStream,
+    _host: &str,
+    _options: &crate::TlsOptions,
+    _timeouts: &TcpStream,
+    _deadline: Option<Deadline>,
+) -> Result<MaybeTlsStream<TcpStream>> {
+    Ok(MaybeTlsStream::Plain(stream))
+}
+
+/// Send the WebSocket upgrade request, and wait for the server to accept it.
+///
+/// `timeouts` is a clone of the TCP stream underneath `stream`, used to enforce the `deadline`.
+fn upgrade(
+    request: tungstenite::handshake::client::Request,
+    stream: MaybeTlsStream<TcpStream>,
+    options: &Options,
+    timeouts: &TcpStream,
+    deadline: Option<Deadline>,
+) -> Result<(Socket, tungstenite::handshake::client::Response)> {
+    set_timeouts(timeouts, deadline)?;
+    let config = websocket_config(options)?;
+    let mut result = tungstenite::client_with_config(request, stream, Some(config));
+    loop {
+        match result {
+            Ok(result) => return Ok(result),
+            Err(tungstenite::HandshakeError::Interrupted(handshake)) => {
+                // A read or write timed out. Go on if there is time left:
+                let Some(deadline) = deadline else {
+                    return Err(Error::Io(Arc::new(std::io::ErrorKind::WouldBlock.into())));
+                };
+                set_timeouts(timeouts, Some(deadline))?;
+                result = handshake.handshake();
+            }
+            Err(tungstenite::HandshakeError::Failure(tungstenite::Error::Io(err))) => {
+                return Err(match deadline {
+                    Some(deadline) if is_timeout(&err) => Error::Timeout(deadline.kind),
+                    _ => Error::Io(Arc::new(err)),
+                });
+            }
+            Err(tungstenite::HandshakeError::Failure(err)) => return Err(err.into()),
+        }
+    }
+}
+
+/// Connect and call the given event handler on each received event.
+///
+/// Blocking version of [`ws_receive`], only avilable on native.
+///
+/// The connection is closed when `on_event` returns [`std::ops::ControlFlow::Break`].
+///
+/// # Errors
+/// * Any connection failures
+pub fn ws_receiver_blocking(url: &str, on_event: &EventHandler) -> Result<()> {
+    ws_receiver_blocking_with_options(url, &Options::default(), on_event)
+}
+
+/// Like [`ws_receiver_blocking`], but with the given [`Options`].
+///
+/// All fields of [`Options`] are supported.
+///
+/// # Errors
+/// * Any connection failures
+pub fn ws_receiver_blocking_with_options(
+    url: &str,
+    options: &Options,
+    on_event: &EventHandler,
+) -> Result<()> {
+    let mut events = Events::new(options, on_event);
+    loop {
+        let ended = receive_once(url, options, &mut events);
+        match events.reconnect(ended) {
+            ControlFlow::Continue(delay) => std::thread::sleep(delay),
+            ControlFlow::Break(result) => return result,
+        }
+    }
+}
+
+/// Connect, and receive until the connection is closed or lost.
+fn receive_once<E: Fn(WsEvent) -> ControlFlow<()>>(
+    url: &str,
+    options: &Options,
+    events: &mut Events<E>,
+) -> Ended {
+    let (mut socket, info) = match connect_socket(url, options) {
+        Ok(result) => result,
+        Err(err) => return events.failed(err),
+    };
+    events.opened(info);
+
+    let mut liveness = Liveness::new(options);
+
+    // Set when we have sent a close frame, and are waiting for the server to acknowledge it.
+    let mut close_deadline: Option<Instant> = None;
+
+    loop {
+        if let Some(close_deadline) = close_deadline {
+            if close_deadline <= Instant::now() {
+                return events.close_timed_out();
+            }
+        } else if events.stop() {
+            log::debug!("Event handler returned Break - closing connection.");
+            socket.close(Some(normal_close_frame())).ok();
+            socket.write_pending().ok();
+            close_deadline = Some(Instant::now() + CLOSE_TIMEOUT);
+        } else if let Err(err) = beat(&mut liveness, &mut socket) {
+            socket.close(None).ok();
+            socket.write_pending().ok();
+            return events.lost(err, false);
+        }
+
+        // Wake up in time for the next heartbeat, to notice when nothing has been received,
+        // and to give up on a server that does not acknowledge our close frame:
+        let wakeup = close_deadline.or_else(|| liveness.next_wakeup());
+        if let Some(wakeup) = wakeup {
+            let timeout = wakeup.saturating_duration_since(Instant::now());
+            let timeout = timeout.max(Duration::from_millis(1));
+            if let Ok(stream) = tcp_stream(&mut socket) {
+                stream.set_read_timeout(Some(timeout)).ok();
+            }
+        }
+
+        match socket.read_message() {
+            Ok(message) => {
+                if let Some(close) = events.received(&mut liveness, message) {
+                    socket.write_pending().ok(); // Acknowledge the close
+                    return events.closed(close, close_deadline.is_some());
+                }
+            }
+            Err(tungstenite::Error::Io(io_err)) if wakeup.is_some() && is_timeout(&io_err) => {
+                // Time for the next heartbeat, to check if we have been idle for too long,
+                // or to give up on closing nicely
+            }
+            Err(err) => return events.lost(err.into(), close_deadline.is_some()),
+        }
+    }
+}
+
+/// Connect on a new thread, and call the given event handler on each received event.
+///
+/// The connection is closed when the last clone of the returned [`WsSender`] is dropped,
+/// or when `on_event` returns [`std::ops::ControlFlow::Break`].
+///
+/// # Errors
+/// * Failure to spawn a thread.
+pub fn ws_connect(url: String, options: Options, on_event: EventHandler) -> Result<WsSender> {
+    let (sender, outgoing) = channel(&options)?;
+
+    std::thread::Builder::new()
+        .name("ewebsock".to_owned())
+        .spawn(move || {
+            let mut events = Events::new(&options, on_event);
+            if let Err(err) = connect_blocking(&url, &options, &mut events, &outgoing) {
+                log::error!("WebSocket error: {err}. Connection closed.");
+            } else {
+                log::debug!("WebSocket connection closed.");
+            }
+        })
+        .map_err(|err| Error::Spawn(Arc::new(err)))?;
+
+    Ok(sender)
+}
+
+/// Connect and call the given event handler on each received event.
+///
+/// This is a blocking variant of [`ws_connect`], only availble on native.
+/// It sends the messages of the [`WsSender`] that came with `outgoing` from [`channel`],
+/// as soon as they are sent.
+///
+/// The connection is closed when that [`WsSender`] is dropped,
+/// or when `on_event` returns [`std::ops::ControlFlow::Break`].
+/// Once this returns, nothing more can be sent with it.
+///
+/// # Errors
+/// * Any connection failures
+pub fn ws_connect_blocking(url: &str, on_event: &EventHandler, outgoing: &Outgoing) -> Result<()> {
+    ws_connect_blocking_with_options(url, &Options::default(), on_event, outgoing)
+}
+
+/// Like [`ws_connect_blocking`], but with the given [`Options`].
+///
+/// All fields of [`Options`] are supported.
+///
+/// # Errors
+/// * Any connection failures
+pub fn ws_connect_blocking_with_options(
+    url: &str,
+    options: &Options,
+    on_event: &EventHandler,
+    outgoing: &Outgoing,
+) -> Result<()> {
+    connect_blocking(url, options, &mut Events::new(options, on_event), outgoing)
+}
+
+/// Connect, and reconnect, until the connection has been closed for good.
+fn connect_blocking<E: Fn(WsEvent) -> ControlFlow<()>>(
+    url: &str,
+    options: &Options,
+    events: &mut Events<E>,
+    outgoing: &Outgoing,
+) -> Result<()> {
+    let Outgoing { rx, shared, .. } = outgoing;
+
+    let result = loop {
+        let ended = connect_and_run(url, options, events, outgoing);
+        let delay = match events.reconnect(ended) {
+            ControlFlow::Continue(delay) => delay,
+            ControlFlow::Break(result) => break result,
+        };
+
+        // Anything not yet written has to wait for the next connection:
+        shared.close(std::iter::from_fn(|| rx.try_recv().ok()));
+
+        // Wait before reconnecting, but stop if the sender is dropped in the meantime:
+        if !wait_to_reconnect(Instant::now() + delay, outgoing) {
+            log::debug!("WsSender dropped while waiting to reconnect.");
+            break Ok(());
+        }
+    };
+
+    // Nothing more will be written:
+    shared.end(std::iter::from_fn(|| rx.try_recv().ok()));
+    result
+}
+
+/// Move the messages sent until `reconnect_time` to the outbox.
+///
+/// Returns `false` if the [`WsSender`] is dropped in the meantime.
+fn wait_to_reconnect(reconnect_time: Instant, outgoing: &Outgoing) -> bool {
+    loop {
+        let timeout = reconnect_time.saturating_duration_since(Instant::now());
+        match outgoing.rx.recv_timeout(timeout) {
+            Ok(message) => outgoing.shared.requeue(message),
+            Err(RecvTimeoutError::Timeout) => return true,
+            Err(RecvTimeoutError::Disconnected) => return false,
+        }
+    }
+}
+
+/// The TCP stream underneath the socket.
+fn tcp_stream(socket: &mut Socket) -> Result<&mut TcpStream> {
+    match socket.get_mut() {
+        MaybeTlsStream::Plain(stream) => Ok(stream),
+        #[cfg(feature = "native-tls")]
+        MaybeTlsStream::NativeTls(stream) => Ok(stream.get_mut()),
+        #[cfg(feature = "tls")]
+        MaybeTlsStream::Rustls(stream) => Ok(stream.get_mut()),
+        stream => Err(Error::Io(Arc::new(std::io::Error::new(
+            std::io::ErrorKind::Unsupported,
+            format!("Unknown tungstenite stream {stream:?}"),
+        )))),
+    }
+}
+
+/// Send a ping if it is time to, and fail if the server has stopped responding.
+fn beat(liveness: &mut Liveness, socket: &mut Socket) -> Result<()> {
+    let Some(ping) = liveness.poll()? else {
+        return Ok(());
+    };
+    match socket.write_message(ping) {
+        Err(tungstenite::Error::Io(err)) if err.kind() == std::io::ErrorKind::WouldBlock => {
+            Ok(()) // Queued, and will be sent later
+        }
+        result => result.map_err(Error::from),
+    }
+}
+
+/// Connect, send the messages in the outbox, and then handle the connection until it ends.
+fn connect_and_run<E: Fn(WsEvent) -> ControlFlow<()>>(
+    url: &str,
+    options: &Options,
+    events: &mut Events<E>,
+    outgoing: &Outgoing,
+) -> Ended {
+    let Outgoing { shared, poller, .. } = outgoing;
+
+    let (mut socket, info) = match connect_socket(url, options) {
+        Ok(result) => result,
+        Err(err) => return events.failed(err),
+    };
+    events.opened(info);
+
+    for message in shared.open(events) {
+        let num_bytes = message.num_bytes();
+        let result = socket.write_message(into_tungstenite_message(message));
+        shared.written(num_bytes);
+        if let Err(err) = result {
+            socket.close(None).ok();
+            socket.write_pending().ok();
+            return events.lost(err.into(), false);
+        }
+    }
+
+    // Wake up when there is something to read:
+    let result = tcp_stream(&mut socket).and_then(|stream| {
+        stream
+            .set_nonblocking(true)
+            .and_then(|()| poller.add(&*stream, Event::readable(SOCKET_KEY)))
+            .map_err(|err| Error::Io(Arc::new(err)))
+    });
+    if let Err(err) = result {
+        return events.lost(err, false);
+    }
+
+    // The bytes of the messages written to the socket, but not yet flushed:
+    let mut unflushed = 0;
+
+    let ended = run(&mut socket, options, events, outgoing, &mut unflushed);
+    shared.written(unflushed);
+
+    if let Ok(stream) = tcp_stream(&mut socket) {
+        poller.delete(&*stream).ok();
+    }
+    ended
+}
+
+/// Handle an open connection until it ends.
+///
+/// Sleeps until the socket is ready, or the [`WsSender`] wakes us up.
+///
+/// Messages are counted in `unflushed` until they have been flushed.
+fn run<E: Fn(WsEvent) -> ControlFlow<()>>(
+    socket: &mut Socket,
+    options: &Options,
+    events: &mut Events<E>,
+    outgoing: &Outgoing,
+    unflushed: &mut usize,
+) -> Ended {
+    let Outgoing { rx, shared, poller } = outgoing;
+
+    let mut liveness = Liveness::new(options);
+
+    // Set when we have sent a close frame, and are waiting for the server to acknowledge it.
+    let mut close_deadline: Option<Instant> = None;
+
+    // Set when the socket could not take everything we wrote to it.
+    let mut want_write = false;
+
+    // Set when we stopped taking messages from `rx` because the socket could not keep up.
+    let mut rx_backlog = false;
+
+    let mut ready = Vec::new();
+
+    loop {
+        if let Some(close_deadline) = close_deadline {
+            if close_deadline <= Instant::now() {
+                return events.close_timed_out();
+            }
+        } else if events.stop() {
+            log::debug!("Event handler returned Break - closing connection.");
+            want_write |= socket.close(Some(normal_close_frame())).is_err();
+            close_deadline = Some(Instant::now() + CLOSE_TIMEOUT);
+        } else if let Err(err) = beat(&mut liveness, socket) {
+            socket.close(None).ok();
+            socket.write_pending().ok();
+            return events.lost(err, false);
+        } else {
+            // Send everything that has been queued up, as long as the socket keeps up:
+            loop {
+                if want_write {
+                    rx_backlog = true;
+                    break;
+                }
+                match rx.try_recv() {
+                    Ok(outgoing_message) => {
+                        shared.dequeue();
+                        *unflushed += outgoing_message.num_bytes();
+                        match socket.write_message(into_tungstenite_message(outgoing_message)) {
+                            Ok(()) => {
+                                shared.written(std::mem::take(unflushed));
+                            }
+                            Err(tungstenite::Error::Io(err))
+                                if err.kind() == std::io::ErrorKind::WouldBlock =>
+                            {
+                                want_write = true;
+                            }
+                            Err(err) => {
+                                socket.close(None).ok();
+                                socket.write_pending().ok();
+                                return events.lost(err.into(), false);
+                            }
+                        }
+                    }
+                    Err(TryRecvError::Disconnected) => {
+                        log::debug!("WsSender dropped - closing connection.");
+                        want_write |= socket.close(shared.take_close_frame()).is_err();
+                        close_deadline = Some(Instant::now() + CLOSE_TIMEOUT);
+                        rx_backlog = false;
+                        break;
+                    }
+                    Err(TryRecvError::Empty) => {
+                        rx_backlog = false;
+                        break;
+                    }
+                }
+            }
+        }
+
+        // Write whatever the socket could not take before:
+        match socket.write_pending() {
+            Ok(()) => {
+                shared.written(std::mem::take(unflushed));
+                want_write = false;
+            }
+            Err(tungstenite::Error::Io(err)) if err.kind() == std::io::ErrorKind::WouldBlock => {
+                want_write = true;
+            }
+            Err(err) if close_deadline.is_none() => return events.lost(err.into(), false),
+            Err(_) => {}
+        }
+
+        // Read everything that has arrived:
+        loop {
+            match socket.read_message() {
+                Ok(message) => {
+                    if let Some(close) = events.received(&mut liveness, message) {
+                        // Acknowledge, if the server initiated the close:
+                        socket.write_pending().ok();
+                        return events.closed(close, close_deadline.is_some());
+                    }
+                }
+                Err(tungstenite::Error::Io(io_err))
+                    if io_err.kind() == std::io::ErrorKind::WouldBlock =>
+                {
+                    break; // Nothing more to read for now
+                }
+                Err(err) => return events.lost(err.into(), close_deadline.is_some()),
+            }
+        }
+
+        // Sleep until there is something to do:
+        let now = Instant::now();
+        let deadlines = [
+            close_deadline,
+            liveness.next_wakeup().filter(|_| close_deadline.is_none()),
+        ];
+        let timeout = deadlines
+            .into_iter()
+            .flatten()
+            .map(|deadline| deadline.saturating_duration_since(now))
+            .chain((events.stop() && close_deadline.is_none()).then_some(Duration::ZERO))
+            .chain(
+                (rx_backlog && !want_write && close_deadline.is_none()).then_some(Duration::ZERO),
+            )
+            .min();
+
+        let interest = Event {
+            key: SOCKET_KEY,
+            readable: true,
+            writable: want_write,
+        };
+        let result = tcp_stream(socket).and_then(|stream| {
+            ready.clear();
+            poller
+                .modify(&*stream, interest)
+                .and_then(|()| poller.wait(&mut ready, timeout))
+                .map_err(|err| Error::Io(Arc::new(err)))
+        });
+        if let Err(err) = result {
+            return events.lost(err, close_deadline.is_some());
+        }
+    }
+}
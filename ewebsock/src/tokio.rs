@@ -0,0 +1,273 @@
+//! The tokio backend, built on [`tokio_tungstenite`].
+//!
+//! Enabled with the `tokio` feature.
+//!
+//! The connections run as tasks on the tokio runtime given to the connect functions,
+//! see [`TokioRuntime`].
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex},
+    task::{Context, Poll},
+    time::Instant,
+};
+
+use tokio::sync::mpsc::{error::TrySendError, Receiver, Sender};
+use tokio_tungstenite::{Connector, MaybeTlsStream};
+use tungstenite::handshake::client::{Request, Response};
+
+#[cfg(feature = "native-tls")]
+use crate::tungstenite_common::native_tls_connector;
+#[cfg(all(feature = "tls", not(feature = "native-tls")))]
+use crate::tungstenite_common::tls_config;
+use crate::{
+    tungstenite_common::{
+        websocket_config, within, ws_connect_async, AsyncRuntime, BoxFuture, Channel, Deadline,
+        SEND_QUEUE_CAPACITY,
+    },
+    Error, EventHandler, Options, Result, TimeoutKind, TlsOptions, WsMessage, WsReceiver,
+};
+
+pub use crate::tungstenite_common::WsSender;
+
+impl Channel for Sender<WsMessage> {
+    fn try_send(&self, message: WsMessage) -> Result<()> {
+        Sender::try_send(self, message).map_err(|err| match err {
+            TrySendError::Full(_) => Error::SendQueueFull,
+            TrySendError::Closed(_) => Error::Closed,
+        })
+    }
+}
+
+/// Runs the connections as tokio tasks.
+struct Tokio;
+
+impl AsyncRuntime for Tokio {
+    type TcpStream = tokio::net::TcpStream;
+    type WebSocketStream = tokio_tungstenite::WebSocketStream<MaybeTlsStream<Self::TcpStream>>;
+    type Sender = Sender<WsMessage>;
+    type Receiver = Receiver<WsMessage>;
+    type Sleep = tokio::time::Sleep;
+
+    fn channel() -> (Self::Sender, Self::Receiver) {
+        tokio::sync::mpsc::channel(SEND_QUEUE_CAPACITY)
+    }
+
+    fn poll_recv(rx: &mut Self::Receiver, cx: &mut Context<'_>) -> Poll<Option<WsMessage>> {
+        rx.poll_recv(cx)
+    }
+
+    fn try_recv(rx: &mut Self::Receiver) -> Option<WsMessage> {
+        rx.try_recv().ok()
+    }
+
+    fn sleep_until(at: Instant) -> Self::Sleep {
+        tokio::time::sleep_until(at.into())
+    }
+
+    fn resolve(host: &str, port: u16) -> BoxFuture<'_, std::io::Result<Vec<SocketAddr>>> {
+        Box::pin(async move { Ok(tokio::net::lookup_host((host, port)).await?.collect()) })
+    }
+
+    fn connect(addr: SocketAddr) -> BoxFuture<'static, std::io::Result<Self::TcpStream>> {
+        Box::pin(tokio::net::TcpStream::connect(addr))
+    }
+
+    fn handshake<'a>(
+        request: Request,
+        stream: Self::TcpStream,
+        _host: &'a str,
+        tls: bool,
+        options: &'a Options,
+        open: Option<Instant>,
+    ) -> BoxFuture<'a, Result<(Self::WebSocketStream, Response)>> {
+        Box::pin(async move {
+            let config = websocket_config(options)?;
+
+            if tls {
+                // tokio-tungstenite does the TLS handshake together with the upgrade:
+                return within::<Self, _>(tls_and_upgrade_deadline(open, options), async {
+                    let connector = tls_connector(&options.tls)?;
+                    tokio_tungstenite::client_async_tls_with_config(
+                        request,
+                        stream,
+                        Some(config),
+                        Some(connector),
+                    )
+                    .await
+                    .map_err(Error::from)
+                })
+                .await;
+            }
+
+            let deadline = Deadline::step(open, options.upgrade_timeout, TimeoutKind::Upgrade);
+            within::<Self, _>(deadline, async {
+                let stream = MaybeTlsStream::Plain(stream);
+                tokio_tungstenite::client_async_with_config(request, stream, Some(config))
+                    .await
+                    .map_err(Error::from)
+            })
+            .await
+        })
+    }
+}
+
+/// The deadline of the TLS handshake and the upgrade together,
+/// see [`Options::tls_handshake_timeout`].
+fn tls_and_upgrade_deadline(
+    open: Option<std::time::Instant>,
+    options: &Options,
+) -> Option<Deadline> {
+    let (timeout, kind) = match (options.tls_handshake_timeout, options.upgrade_timeout) {
+        (Some(tls), Some(upgrade)) => (Some(tls + upgrade), TimeoutKind::Upgrade),
+        (Some(tls), None) => (Some(tls), TimeoutKind::TlsHandshake),
+        (None, upgrade) => (upgrade, TimeoutKind::Upgrade),
+    };
+    Deadline::step(open, timeout, kind)
+}
+
+/// How tokio-tungstenite should establish TLS, using the TLS library of the operating system.
+#[cfg(feature = "native-tls")]
+fn tls_connector(options: &TlsOptions) -> Result<Connector> {
+    Ok(Connector::NativeTls(native_tls_connector(options)?))
+}
+
+/// How tokio-tungstenite should establish TLS, using rustls.
+#[cfg(all(feature = "tls", not(feature = "native-tls")))]
+fn tls_connector(options: &TlsOptions) -> Result<Connector> {
+    Ok(Connector::Rustls(tls_config(options)?))
+}
+
+/// Without the `tls` or `native-tls` feature,
+/// [`crate::tungstenite_common::is_tls`] fails before we get here.
+#[cfg(not(any(feature = "tls", feature = "native-tls")))]
+fn tls_connector(_options: &TlsOptions) -> Result<Connector> {
+    Err(Error::Url(
+        "`wss://` URLs need the `tls` or `native-tls` feature of ewebsock".to_owned(),
+    ))
+}
+
+/// Which tokio runtime runs the connections.
+///
+/// Given to [`connect`], [`ws_connect`] and [`ws_receive`].
+#[derive(Clone, Debug, Default)]
+pub enum TokioRuntime {
+    /// The runtime of the thread that connects.
+    ///
+    /// Connecting from outside a tokio runtime fails with [`Error::Options`].
+    #[default]
+    Current,
+
+    /// The given runtime.
+    Handle(tokio::runtime::Handle),
+
+    /// A small runtime on a background thread, shared by all connections that use it.
+    ///
+    /// It is started when first needed, and never stopped.
+    /// Use this to connect from threads without a runtime, e.g. the UI thread of a GUI app.
+    Shared,
+}
+
+impl TokioRuntime {
+    fn handle(&self) -> Result<tokio::runtime::Handle> {
+        match self {
+            Self::Current => tokio::runtime::Handle::try_current().map_err(|_| {
+                Error::Options(
+                    "Not inside a tokio runtime. \
+                    Use `TokioRuntime::Shared` or a runtime handle instead."
+                        .to_owned(),
+                )
+            }),
+            Self::Handle(handle) => Ok(handle.clone()),
+            Self::Shared => shared_runtime(),
+        }
+    }
+}
+
+/// The runtime of [`TokioRuntime::Shared`], started on first use.
+fn shared_runtime() -> Result<tokio::runtime::Handle> {
+    static SHARED: Mutex<Option<tokio::runtime::Handle>> = Mutex::new(None);
+
+    let mut shared = SHARED
+        .lock()
+        .unwrap_or_else(|poisoned| poisoned.into_inner());
+    if let Some(handle) = &*shared {
+        return Ok(handle.clone());
+    }
+
+    let runtime = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map_err(|err| Error::Io(Arc::new(err)))?;
+    let handle = runtime.handle().clone();
+    std::thread::Builder::new()
+        .name("ewebsock-tokio".to_owned())
+        .spawn(move || runtime.block_on(std::future::pending::<()>()))
+        .map_err(|err| Error::Spawn(Arc::new(err)))?;
+
+    *shared = Some(handle.clone());
+    Ok(handle)
+}
+
+/// Connect to the given URL on the given tokio runtime, and return a sender and receiver.
+///
+/// All fields of [`Options`] are supported.
+///
+/// # Errors
+/// * [`Error::Options`] if there is no tokio runtime to connect on.
+pub fn connect(
+    url: impl Into<String>,
+    options: Options,
+    runtime: TokioRuntime,
+) -> Result<(WsSender, WsReceiver)> {
+    let (receiver, on_event) = WsReceiver::new();
+    let sender = ws_connect(url.into(), options, runtime, on_event)?;
+    Ok((sender, receiver))
+}
+
+/// Connect on the given tokio runtime, and call the given event handler on each received event.
+///
+/// The connection is closed when the last clone of the returned [`WsSender`] is dropped,
+/// or when `on_event` returns [`std::ops::ControlFlow::Break`].
+///
+/// # Errors
+/// * [`Error::Options`] if there is no tokio runtime to connect on.
+pub fn ws_connect(
+    url: String,
+    options: Options,
+    runtime: TokioRuntime,
+    on_event: EventHandler,
+) -> Result<WsSender> {
+    let runtime = runtime.handle()?;
+    Ok(ws_connect_native(url, options, on_event, &runtime))
+}
+
+/// Like [`ws_connect`], but on the given runtime, so it cannot fail.
+fn ws_connect_native(
+    url: String,
+    options: Options,
+    on_event: EventHandler,
+    runtime: &tokio::runtime::Handle,
+) -> WsSender {
+    let (sender, connection) = ws_connect_async::<Tokio>(url, options, on_event);
+    runtime.spawn(connection);
+    sender
+}
+
+/// Connect on the given tokio runtime, and call the given event handler on each received event.
+///
+/// Like [`ws_connect`], but it doesn't return a [`WsSender`],
+/// so it can only receive messages, not send them.
+///
+/// The connection is closed when `on_event` returns [`std::ops::ControlFlow::Break`].
+///
+/// # Errors
+/// * [`Error::Options`] if there is no tokio runtime to connect on.
+pub fn ws_receive(
+    url: String,
+    options: Options,
+    runtime: TokioRuntime,
+    on_event: EventHandler,
+) -> Result<()> {
+    ws_connect(url, options, runtime, on_event).map(|sender| sender.forget())
+}
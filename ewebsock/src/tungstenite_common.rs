@@ -0,0 +1,1430 @@
+use std::{
+    collections::VecDeque,
+    ops::ControlFlow,
+    pin::Pin,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc, Mutex,
+    },
+    task::{Context, Poll, Waker},
+    time::{Duration, Instant},
+};
+#[cfg(any(feature = "tokio", feature = "agnostic"))]
+use std::{
+    future::{poll_fn, Future},
+    net::SocketAddr,
+};
+
+use tungstenite::{
+    client::IntoClientRequest as _,
+    handshake::client::{Request, Response},
+    http::{HeaderName, HeaderValue},
+    protocol::CloseFrame,
+};
+
+use crate::{
+    heartbeat::{Beat, Heartbeat},
+    outbox::Outbox,
+    reconnect::Backoff,
+    CloseInfo, ConnectionInfo, Error, Options, Result, TimeoutKind, WsEvent, WsMessage,
+};
+
+#[cfg(any(feature = "tokio", feature = "agnostic"))]
+use crate::EventHandler;
+#[cfg(any(feature = "tls", feature = "native-tls"))]
+use crate::{Certificate, PrivateKey, TlsOptions};
+
+impl From<tungstenite::Error> for Error {
+    fn from(err: tungstenite::Error) -> Self {
+        use tungstenite::Error as TError;
+
+        match err {
+            TError::ConnectionClosed | TError::AlreadyClosed => Self::Closed,
+            TError::Io(err) => Self::Io(Arc::new(err)),
+            TError::Tls(err) => Self::Tls(Arc::new(err)),
+            TError::Capacity(tungstenite::error::CapacityError::MessageTooLong {
+                size,
+                max_size,
+            }) => Self::MessageTooLarge { size, max_size },
+            TError::Capacity(err) => Self::Capacity(Arc::new(err)),
+            TError::Protocol(err) => Self::Protocol(Arc::new(err)),
+            err @ TError::WriteBufferFull(_) => Self::Capacity(Arc::new(err)),
+            err @ (TError::Utf8 | TError::AttackAttempt) => Self::Protocol(Arc::new(err)),
+            TError::Url(err) => Self::Url(err.to_string()),
+            TError::Http(response) => Self::HandshakeRejected {
+                status: response.status().as_u16(),
+            },
+            TError::HttpFormat(err) => Self::Protocol(Arc::new(err)),
+        }
+    }
+}
+
+/// The handshake request for the given URL, including any extra headers from the [`Options`].
+pub(crate) fn into_requester(url: &str, options: &Options) -> Result<Request> {
+    let mut request = url.into_client_request()?;
+
+    for (name, value) in &options.additional_headers {
+        let header_name = HeaderName::from_bytes(name.as_bytes())
+            .map_err(|err| Error::Options(format!("Invalid header name {name:?}: {err}")))?;
+        let header_value = HeaderValue::from_str(value)
+            .map_err(|err| Error::Options(format!("Invalid value for header {name:?}: {err}")))?;
+        request.headers_mut().append(header_name, header_value);
+    }
+
+    if !options.subprotocols.is_empty() {
+        let protocols = options.subprotocols.join(", ");
+        let header_value = HeaderValue::from_str(&protocols)
+            .map_err(|err| Error::Options(format!("Invalid subprotocols {protocols:?}: {err}")))?;
+        request
+            .headers_mut()
+            .insert("Sec-WebSocket-Protocol", header_value);
+    }
+
+    Ok(request)
+}
+
+/// The host and port to open a TCP connection to for the given request.
+pub(crate) fn host_and_port(request: &Request) -> Result<(String, u16)> {
+    let uri = request.uri();
+    let host = uri
+        .host()
+        .ok_or_else(|| Error::Url(format!("No host name in {uri}")))?;
+    let port = uri.port_u16().unwrap_or(match uri.scheme_str() {
+        Some("wss") => 443,
+        _ => 80,
+    });
+
+    // IPv6 addresses are written in brackets in URLs, but not in socket addresses:
+    let host = host.trim_start_matches('[').trim_end_matches(']');
+
+    Ok((host.to_owned(), port))
+}
+
+/// Whether the request is for a `wss://` URL.
+///
+/// Fails for `wss://` URLs without the `tls` or `native-tls` feature.
+pub(crate) fn is_tls(request: &Request) -> Result<bool> {
+    match request.uri().scheme_str() {
+        Some("wss") if cfg!(any(feature = "tls", feature = "native-tls")) => Ok(true),
+        Some("wss") => Err(Error::Url(
+            "`wss://` URLs need the `tls` or `native-tls` feature of ewebsock".to_owned(),
+        )),
+        _ => Ok(false),
+    }
+}
+
+/// The rustls configuration of `wss://` connections.
+#[cfg(feature = "tls")]
+#[cfg_attr(
+    all(feature = "native-tls", not(feature = "agnostic")),
+    allow(dead_code) // native-tls is used instead
+)]
+pub(crate) fn tls_config(options: &TlsOptions) -> Result<Arc<rustls::ClientConfig>> {
+    let mut roots = rustls::RootCertStore::empty();
+    if options.native_roots {
+        let certs =
+            rustls_native_certs::load_native_certs().map_err(|err| Error::Tls(Arc::new(err)))?;
+        let certs: Vec<Vec<u8>> = certs.into_iter().map(|cert| cert.0).collect();
+        let (_, ignored) = roots.add_parsable_certificates(&certs);
+        if 0 < ignored {
+            log::debug!("Ignored {ignored} native root certificate(s) that rustls could not parse");
+        }
+    } else {
+        roots.add_trust_anchors(webpki_roots::TLS_SERVER_ROOTS.iter().map(|anchor| {
+            rustls::OwnedTrustAnchor::from_subject_spki_name_constraints(
+                anchor.subject,
+                anchor.spki,
+                anchor.name_constraints,
+            )
+        }));
+    }
+    for cert in rustls_certificates(&options.root_certificates)? {
+        roots
+            .add(&cert)
+            .map_err(|err| Error::Options(format!("Invalid root certificate: {err}")))?;
+    }
+
+    let builder = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots);
+    let config = match &options.client_identity {
+        Some(identity) => builder
+            .with_client_auth_cert(
+                rustls_certificates(&identity.certificates)?,
+                rustls_private_key(&identity.private_key)?,
+            )
+            .map_err(|err| Error::Options(format!("Invalid client identity: {err}")))?,
+        None => builder.with_no_client_auth(),
+    };
+    Ok(Arc::new(config))
+}
+
+#[cfg(feature = "tls")]
+fn rustls_certificates(certificates: &[Certificate]) -> Result<Vec<rustls::Certificate>> {
+    let mut parsed = Vec::new();
+    for certificate in certificates {
+        match certificate {
+            Certificate::Pem(pem) => {
+                let ders = rustls_pemfile::certs(&mut pem.as_slice())
+                    .map_err(|err| Error::Options(format!("Invalid PEM certificate: {err}")))?;
+                if ders.is_empty() {
+                    return Err(Error::Options("No certificates found in PEM".to_owned()));
+                }
+                parsed.extend(ders.into_iter().map(rustls::Certificate));
+            }
+            Certificate::Der(der) => parsed.push(rustls::Certificate(der.clone())),
+        }
+    }
+    Ok(parsed)
+}
+
+#[cfg(feature = "tls")]
+fn rustls_private_key(key: &PrivateKey) -> Result<rustls::PrivateKey> {
+    match key {
+        PrivateKey::Pem(pem) => {
+            let mut reader = pem.as_slice();
+            loop {
+                match rustls_pemfile::read_one(&mut reader)
+                    .map_err(|err| Error::Options(format!("Invalid PEM private key: {err}")))?
+                {
+                    Some(
+                        rustls_pemfile::Item::PKCS8Key(der)
+                        | rustls_pemfile::Item::RSAKey(der)
+                        | rustls_pemfile::Item::ECKey(der),
+                    ) => return Ok(rustls::PrivateKey(der)),
+                    Some(_) => {} // e.g. a certificate
+                    None => {
+                        return Err(Error::Options("No private key found in PEM".to_owned()));
+                    }
+                }
+            }
+        }
+        PrivateKey::Der(der) => Ok(rustls::PrivateKey(der.clone())),
+    }
+}
+
+/// The native-tls configuration of `wss://` connections.
+#[cfg(feature = "native-tls")]
+pub(crate) fn native_tls_connector(options: &TlsOptions) -> Result<native_tls::TlsConnector> {
+    let mut builder = native_tls::TlsConnector::builder();
+    for certificate in &options.root_certificates {
+        let parsed = match certificate {
+            Certificate::Pem(pem) => pem_certificates(pem)?
+                .map(native_tls::Certificate::from_pem)
+                .collect(),
+            Certificate::Der(der) => vec![native_tls::Certificate::from_der(der)],
+        };
+        for cert in parsed {
+            let cert =
+                cert.map_err(|err| Error::Options(format!("Invalid root certificate: {err}")))?;
+            builder.add_root_certificate(cert);
+        }
+    }
+
+    if let Some(identity) = &options.client_identity {
+        let need_pem = || {
+            Error::Options(
+                "native-tls needs the client certificates and private key in PEM".to_owned(),
+            )
+        };
+        let mut chain = Vec::new();
+        for certificate in &identity.certificates {
+            let Certificate::Pem(pem) = certificate else {
+                return Err(need_pem());
+            };
+            chain.extend_from_slice(pem);
+            chain.push(b'\n');
+        }
+        let PrivateKey::Pem(key) = &identity.private_key else {
+            return Err(need_pem());
+        };
+        let identity = native_tls::Identity::from_pkcs8(&chain, key)
+            .map_err(|err| Error::Options(format!("Invalid client identity: {err}")))?;
+        builder.identity(identity);
+    }
+
+    builder.build().map_err(|err| Error::Tls(Arc::new(err)))
+}
+
+/// Split PEM-encoded certificates, since native-tls parses them one at a time.
+#[cfg(feature = "native-tls")]
+fn pem_certificates(pem: &[u8]) -> Result<impl Iterator<Item = &[u8]>> {
+    const END: &str = "-----END CERTIFICATE-----";
+    let pem = std::str::from_utf8(pem)
+        .map_err(|err| Error::Options(format!("Invalid PEM certificate: {err}")))?;
+    if !pem.contains(END) {
+        return Err(Error::Options("No certificates found in PEM".to_owned()));
+    }
+    Ok(pem
+        .split_inclusive(END)
+        .filter(|block| block.contains(END))
+        .map(str::as_bytes))
+}
+
+/// The name to verify the certificate of the server against.
+#[cfg(feature = "tls")]
+#[cfg_attr(
+    all(feature = "native-tls", not(feature = "agnostic")),
+    allow(dead_code) // native-tls is used instead
+)]
+pub(crate) fn server_name(host: &str) -> Result<rustls::ServerName> {
+    rustls::ServerName::try_from(host).map_err(|err| Error::Tls(Arc::new(err)))
+}
+
+/// When a step of opening a connection has to be done by,
+/// and what to report if it is not.
+#[derive(Clone, Copy, Debug)]
+pub(crate) struct Deadline {
+    pub at: Instant,
+    pub kind: TimeoutKind,
+}
+
+impl Deadline {
+    /// The deadline of a step that may take `timeout`,
+    /// or that of the whole opening (see [`Options::open_timeout`]) if that comes first.
+    pub fn step(
+        open: Option<Instant>,
+        timeout: Option<Duration>,
+        kind: TimeoutKind,
+    ) -> Option<Self> {
+        let step = timeout.map(|timeout| Self {
+            at: Instant::now() + timeout,
+            kind,
+        });
+        let open = open.map(|at| Self {
+            at,
+            kind: TimeoutKind::Open,
+        });
+        match (step, open) {
+            (Some(step), Some(open)) => Some(if open.at < step.at { open } else { step }),
+            (step, open) => step.or(open),
+        }
+    }
+
+    /// The time left, or an [`Error::Timeout`] if there is none.
+    pub fn time_left(&self) -> Result<Duration> {
+        let left = self.at.saturating_duration_since(Instant::now());
+        if left.is_zero() {
+            Err(Error::Timeout(self.kind))
+        } else {
+            Ok(left)
+        }
+    }
+}
+
+/// What we know about the connection, based on the handshake response.
+///
+/// Fails if the server picked a subprotocol that was not offered in [`Options::subprotocols`].
+pub(crate) fn connection_info(options: &Options, response: &Response) -> Result<ConnectionInfo> {
+    log::debug!("WebSocket HTTP response code: {}", response.status());
+    log::trace!(
+        "WebSocket response contains the following headers: {:?}",
+        response.headers()
+    );
+
+    let headers = response
+        .headers()
+        .iter()
+        .map(|(name, value)| {
+            (
+                name.as_str().to_owned(),
+                String::from_utf8_lossy(value.as_bytes()).into_owned(),
+            )
+        })
+        .collect();
+
+    let extensions = response
+        .headers()
+        .get("Sec-WebSocket-Extensions")
+        .map(|value| String::from_utf8_lossy(value.as_bytes()).into_owned());
+
+    Ok(ConnectionInfo {
+        status: Some(response.status().as_u16()),
+        headers,
+        protocol: negotiated_protocol(options, response)?,
+        extensions,
+    })
+}
+
+/// The subprotocol the server picked, if any.
+///
+/// Fails if the server picked a subprotocol that was not offered in [`Options::subprotocols`].
+fn negotiated_protocol(options: &Options, response: &Response) -> Result<Option<String>> {
+    let Some(header_value) = response.headers().get("Sec-WebSocket-Protocol") else {
+        return Ok(None);
+    };
+    let protocol = header_value
+        .to_str()
+        .map_err(|err| Error::protocol(format!("Invalid Sec-WebSocket-Protocol header: {err}")))?;
+
+    if options
+        .subprotocols
+        .iter()
+        .any(|offered| offered == protocol)
+    {
+        Ok(Some(protocol.to_owned()))
+    } else {
+        Err(Error::protocol(format!(
+            "The server picked the subprotocol {protocol:?}, which was not offered (offered: {:?})",
+            options.subprotocols
+        )))
+    }
+}
+
+/// The tungstenite configuration corresponding to the given [`Options`].
+///
+/// Fails if [`Options::max_write_buffer_size`] is not larger than [`Options::write_buffer_size`].
+pub(crate) fn websocket_config(
+    options: &Options,
+) -> Result<tungstenite::protocol::WebSocketConfig> {
+    let mut config = tungstenite::protocol::WebSocketConfig::default();
+    if let Some(max_message_size) = options.max_message_size {
+        config.max_message_size = Some(max_message_size);
+    }
+    if let Some(max_frame_size) = options.max_frame_size {
+        config.max_frame_size = Some(max_frame_size);
+    }
+    if let Some(write_buffer_size) = options.write_buffer_size {
+        config.write_buffer_size = write_buffer_size;
+    }
+    if let Some(max_write_buffer_size) = options.max_write_buffer_size {
+        config.max_write_buffer_size = max_write_buffer_size;
+    }
+    // tungstenite panics otherwise:
+    if config.max_write_buffer_size <= config.write_buffer_size {
+        return Err(Error::Options(format!(
+            "`max_write_buffer_size` ({}) must be larger than `write_buffer_size` ({})",
+            config.max_write_buffer_size, config.write_buffer_size
+        )));
+    }
+    Ok(config)
+}
+
+/// How long to wait for the other side to acknowledge our close frame.
+pub(crate) const CLOSE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5);
+
+/// How many messages can wait to be written to an open connection.
+pub(crate) const SEND_QUEUE_CAPACITY: usize = 1000;
+
+/// Keeps track of the messages that have been sent to the connection,
+/// but not yet written to the socket,
+/// and wakes up whoever is waiting for them (see `futures_sink::Sink`).
+#[derive(Default)]
+struct InFlight {
+    /// The bytes not yet written to the socket.
+    num_bytes: AtomicUsize,
+
+    /// The number of messages not yet taken out of the channel to the connection.
+    queued: AtomicUsize,
+
+    wakers: Mutex<Vec<Waker>>,
+}
+
+impl InFlight {
+    /// Call before sending a message to the connection.
+    fn queue(&self, num_bytes: usize) {
+        self.num_bytes.fetch_add(num_bytes, Ordering::Relaxed);
+        self.queued.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Call when a message has been taken out of the channel.
+    fn dequeue(&self) {
+        self.queued.fetch_sub(1, Ordering::Relaxed);
+        self.wake();
+    }
+
+    /// Call when bytes have been written to the socket (or never will be).
+    fn done(&self, num_bytes: usize) {
+        self.num_bytes.fetch_sub(num_bytes, Ordering::Relaxed);
+        self.wake();
+    }
+
+    /// The bytes not yet written to the socket.
+    fn num_bytes(&self) -> usize {
+        self.num_bytes.load(Ordering::Relaxed)
+    }
+
+    /// Is there room for another message in the channel?
+    fn has_room(&self) -> bool {
+        self.queued.load(Ordering::Relaxed) < SEND_QUEUE_CAPACITY
+    }
+
+    /// Wake up the task of `cx` on the next change.
+    fn register(&self, cx: &Context<'_>) {
+        if let Ok(mut wakers) = self.wakers.lock() {
+            if !wakers.iter().any(|waker| waker.will_wake(cx.waker())) {
+                wakers.push(cx.waker().clone());
+            }
+        }
+    }
+
+    fn wake(&self) {
+        let wakers = self
+            .wakers
+            .lock()
+            .map(|mut wakers| std::mem::take(&mut *wakers))
+            .unwrap_or_default();
+        for waker in wakers {
+            waker.wake();
+        }
+    }
+}
+
+/// Convert an outgoing [`WsMessage`] to a tungstenite message.
+pub(crate) fn into_tungstenite_message(message: WsMessage) -> tungstenite::Message {
+    match message {
+        WsMessage::Text(text) => tungstenite::Message::Text(text),
+        WsMessage::Binary(data) => tungstenite::Message::Binary(data),
+        WsMessage::Ping(data) => tungstenite::Message::Ping(data),
+        WsMessage::Pong(data) => tungstenite::Message::Pong(data),
+        WsMessage::Unknown(_) => unreachable!("Rejected by WsSender::try_send"),
+    }
+}
+
+/// The close frame to send for the given code and reason.
+pub(crate) fn close_frame(code: u16, reason: String) -> CloseFrame<'static> {
+    CloseFrame {
+        code: code.into(),
+        reason: reason.into(),
+    }
+}
+
+/// The close frame to send when the event handler returns [`std::ops::ControlFlow::Break`].
+pub(crate) fn normal_close_frame() -> CloseFrame<'static> {
+    close_frame(1000, String::new())
+}
+
+/// Describe a received close frame.
+pub(crate) fn close_info(frame: Option<CloseFrame<'_>>) -> CloseInfo {
+    match frame {
+        Some(frame) => CloseInfo {
+            code: frame.code.into(),
+            reason: frame.reason.into_owned(),
+            was_clean: true,
+        },
+        None => CloseInfo {
+            code: 1005, // No status received
+            reason: String::new(),
+            was_clean: true,
+        },
+    }
+}
+
+/// The sending half of the channel from a [`WsSender`] to its connection.
+///
+/// Dropping it tells the connection to close.
+pub(crate) trait Channel: Send {
+    /// Send a message to the connection without waiting.
+    ///
+    /// Fails with [`Error::SendQueueFull`] or [`Error::Closed`].
+    fn try_send(&self, message: WsMessage) -> Result<()>;
+}
+
+/// This is how you send [`WsMessage`]s to the server.
+///
+/// This can be cloned, and shared between threads.
+/// When the last clone of this is dropped, the connection is closed.
+#[derive(Clone)]
+pub struct WsSender {
+    inner: Arc<Inner>,
+}
+
+/// The state shared by all clones of a [`WsSender`].
+struct Inner {
+    /// Taken when the connection is closed.
+    tx: Mutex<Option<Box<dyn Channel>>>,
+
+    shared: Arc<Shared>,
+}
+
+impl Drop for Inner {
+    fn drop(&mut self) {
+        self.close();
+    }
+}
+
+impl Inner {
+    fn take_tx(&self) -> Option<Box<dyn Channel>> {
+        let tx = self.tx.lock().ok().and_then(|mut tx| tx.take());
+        // Whoever waits on the sink should notice:
+        self.shared.in_flight.wake();
+        tx
+    }
+
+    /// Drop `tx`, so that the connection is closed.
+    fn close(&self) {
+        if self.take_tx().is_some() {
+            log::debug!("Closing WebSocket");
+        }
+    }
+
+    fn is_closed(&self) -> bool {
+        self.tx.lock().map_or(true, |tx| tx.is_none())
+    }
+
+    /// Ready once `check` returns `Some`, woken up whenever the outbox or the channel changes.
+    fn poll_until(
+        &self,
+        cx: &Context<'_>,
+        check: impl Fn(&Self) -> Option<Result<()>>,
+    ) -> Poll<Result<()>> {
+        if let Some(result) = check(self) {
+            return Poll::Ready(result);
+        }
+        self.shared.in_flight.register(cx);
+        // It may have changed before the waker was registered:
+        check(self).map_or(Poll::Pending, Poll::Ready)
+    }
+
+    /// `Some` once a message can be sent straight to the open connection.
+    fn ready(&self) -> Option<Result<()>> {
+        if self.is_closed() {
+            return Some(Err(Error::Closed));
+        }
+        let Ok(outbox) = self.shared.outbox.lock() else {
+            return Some(Err(Error::Closed));
+        };
+        if outbox.is_ended() {
+            Some(Err(Error::Closed))
+        } else if outbox.is_open() && self.shared.in_flight.has_room() {
+            Some(Ok(()))
+        } else {
+            None
+        }
+    }
+
+    /// `Some` once everything sent has been written to the socket,
+    /// including the messages in the outbox.
+    fn flushed(&self) -> Option<Result<()>> {
+        let Ok(outbox) = self.shared.outbox.lock() else {
+            return Some(Err(Error::Closed));
+        };
+        // Holding the lock, so that no messages move between the outbox and the channel meanwhile.
+        let buffered = outbox.num_bytes() + self.shared.in_flight.num_bytes();
+        (buffered == 0).then_some(Ok(()))
+    }
+}
+
+impl WsSender {
+    pub(crate) fn new(tx: impl Channel + 'static, shared: Arc<Shared>) -> Self {
+        Self {
+            inner: Arc::new(Inner {
+                tx: Mutex::new(Some(Box::new(tx))),
+                shared,
+            }),
+        }
+    }
+
+    /// Send a message.
+    ///
+    /// Messages sent before [`WsEvent::Opened`], or while reconnecting,
+    /// are buffered and sent once the connection opens. See [`Options::outbox`].
+    ///
+    /// Messages are sent in the order this is called.
+    /// This can be called from any thread, with or without an async runtime.
+    ///
+    /// Errors are logged. Use [`Self::try_send`] to handle them yourself.
+    pub fn send(&self, msg: WsMessage) {
+        if let Err(err) = self.try_send(msg) {
+            log::error!("Failed to send: {err}");
+        }
+    }
+
+    /// Send a message, or fail if it cannot be.
+    ///
+    /// # Errors
+    /// * [`Error::Closed`] if the connection is closed, and will not be reconnected.
+    /// * [`Error::SendQueueFull`] if too many messages are waiting to be written to the connection.
+    /// * [`Error::OutboxFull`] if the connection is not open,
+    ///   and the outbox is full (see [`crate::OverflowPolicy::Error`]).
+    /// * [`Error::InvalidMessage`] for a [`WsMessage::Unknown`].
+    pub fn try_send(&self, msg: WsMessage) -> Result<()> {
+        if let WsMessage::Unknown(_) = msg {
+            return Err(Error::InvalidMessage(
+                "`WsMessage::Unknown` cannot be sent".to_owned(),
+            ));
+        }
+        let inner = &*self.inner;
+        let Ok(tx) = inner.tx.lock() else {
+            return Err(Error::Closed);
+        };
+        let Some(tx) = &*tx else {
+            return Err(Error::Closed);
+        };
+        let Ok(mut outbox) = inner.shared.outbox.lock() else {
+            return Err(Error::Closed);
+        };
+        // Holding the lock, so that the connection cannot open or close in the meantime.
+        if !outbox.is_open() {
+            return outbox.push(msg);
+        }
+
+        let num_bytes = msg.num_bytes();
+        inner.shared.in_flight.queue(num_bytes);
+        tx.try_send(msg).map_err(|err| {
+            inner.shared.in_flight.dequeue();
+            inner.shared.in_flight.done(num_bytes);
+            err
+        })
+    }
+
+    /// The number of bytes that have been sent, but not yet written to the connection.
+    ///
+    /// This includes messages waiting in the outbox, in the queue to the connection,
+    /// and in the write buffer of the socket.
+    /// Use this to slow down if the connection cannot keep up.
+    pub fn buffered_amount(&self) -> usize {
+        let shared = &self.inner.shared;
+        let outbox = shared.outbox.lock().map_or(0, |outbox| outbox.num_bytes());
+        outbox + shared.in_flight.num_bytes()
+    }
+
+    /// Close the connection.
+    ///
+    /// This closes it for all clones of this sender.
+    /// It is called automatically when the last clone is dropped.
+    pub fn close(&self) -> Result<()> {
+        self.inner.close();
+        Ok(())
+    }
+
+    /// Close the connection with the given close code and reason.
+    ///
+    /// The code must be `1000` (normal closure) or in the range `3000`–`4999`,
+    /// and the reason must be at most 123 bytes long.
+    ///
+    /// The server acknowledges the close with a [`WsEvent::Closed`].
+    pub fn close_with(&self, code: u16, reason: impl Into<String>) -> Result<()> {
+        let reason = reason.into();
+        crate::check_close_frame(code, &reason)?;
+        if let Some(tx) = self.inner.take_tx() {
+            log::debug!("Closing WebSocket with code {code}");
+            if let Ok(mut frame) = self.inner.shared.close_frame.lock() {
+                *frame = Some(close_frame(code, reason));
+            }
+            drop(tx);
+        }
+        Ok(())
+    }
+
+    /// Forget about this sender without closing the connection.
+    ///
+    /// The connection can still be closed with [`Self::close`] on another clone.
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+}
+
+/// Sends the messages like [`WsSender::try_send`].
+///
+/// `poll_ready` waits until the connection is open, and there is room in the queue to it,
+/// so a sink never fills up the outbox.
+/// `poll_flush` waits until all messages, including those in the outbox,
+/// have been written to the socket, and `poll_close` flushes before it closes the connection.
+impl futures_sink::Sink<WsMessage> for WsSender {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.inner.poll_until(cx, Inner::ready)
+    }
+
+    fn start_send(self: Pin<&mut Self>, msg: WsMessage) -> Result<()> {
+        self.try_send(msg)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        self.inner.poll_until(cx, Inner::flushed)
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        std::task::ready!(self.as_mut().poll_flush(cx))?;
+        Poll::Ready(self.close())
+    }
+}
+
+/// What a [`WsSender`] shares with its connection.
+pub(crate) struct Shared {
+    /// The close frame to send when the [`WsSender`] is closed.
+    close_frame: Mutex<Option<CloseFrame<'static>>>,
+
+    /// Messages sent while the connection is not open.
+    outbox: Mutex<Outbox>,
+
+    /// The messages sent through the channel, but not yet written to the socket.
+    in_flight: InFlight,
+}
+
+impl Shared {
+    pub fn new(options: &Options) -> Arc<Self> {
+        Arc::new(Self {
+            close_frame: Mutex::new(None),
+            outbox: Mutex::new(Outbox::new(options)),
+            in_flight: InFlight::default(),
+        })
+    }
+
+    /// Call when the connection opens, and send the returned messages first.
+    ///
+    /// From then on, new messages go through the channel.
+    /// Reports the messages dropped from the outbox, if any.
+    pub fn open<E: Fn(WsEvent) -> ControlFlow<()>>(&self, events: &mut Events<E>) -> Unsent<'_> {
+        let (dropped, messages) = match self.outbox.lock() {
+            Ok(mut outbox) => {
+                let (dropped, messages) = outbox.open();
+                // Still buffered, until written:
+                let num_bytes = messages.iter().map(WsMessage::num_bytes).sum();
+                self.in_flight
+                    .num_bytes
+                    .fetch_add(num_bytes, Ordering::Relaxed);
+                (dropped, messages)
+            }
+            Err(_) => Default::default(),
+        };
+        self.in_flight.wake();
+        if 0 < dropped {
+            events.emit(WsEvent::OutgoingDropped { count: dropped });
+        }
+        Unsent {
+            shared: self,
+            messages,
+        }
+    }
+
+    /// Call when a message has been taken out of the channel.
+    pub fn dequeue(&self) {
+        self.in_flight.dequeue();
+    }
+
+    /// Call when bytes have been written to the socket (or never will be).
+    pub fn written(&self, num_bytes: usize) {
+        self.in_flight.done(num_bytes);
+    }
+
+    /// Call when the connection fails or is lost.
+    ///
+    /// The `unsent` messages, still in the channel, have to wait for the next connection.
+    pub fn close(&self, unsent: impl Iterator<Item = WsMessage>) {
+        if let Ok(mut outbox) = self.outbox.lock() {
+            outbox.close();
+            for message in unsent {
+                self.in_flight.dequeue();
+                self.in_flight.done(message.num_bytes());
+                outbox.requeue(message);
+            }
+        }
+        self.in_flight.wake();
+    }
+
+    /// Call with a message taken out of the channel while waiting to reconnect.
+    pub fn requeue(&self, message: WsMessage) {
+        if let Ok(mut outbox) = self.outbox.lock() {
+            self.in_flight.dequeue();
+            self.in_flight.done(message.num_bytes());
+            outbox.requeue(message);
+        }
+    }
+
+    /// Call when the connection has ended for good.
+    ///
+    /// The `unsent` messages, still in the channel, will never be written.
+    pub fn end(&self, unsent: impl Iterator<Item = WsMessage>) {
+        if let Ok(mut outbox) = self.outbox.lock() {
+            outbox.end();
+            for message in unsent {
+                self.in_flight.dequeue();
+                self.in_flight.done(message.num_bytes());
+            }
+        }
+        self.in_flight.wake();
+    }
+
+    /// The close frame given to [`WsSender::close_with`], if any.
+    pub fn take_close_frame(&self) -> Option<CloseFrame<'static>> {
+        self.close_frame
+            .lock()
+            .ok()
+            .and_then(|mut frame| frame.take())
+    }
+}
+
+/// The messages from the outbox, to send first when the connection opens.
+///
+/// They are buffered until [`Shared::written`] is called with their size,
+/// or until this is dropped with them still in it.
+pub(crate) struct Unsent<'a> {
+    shared: &'a Shared,
+    messages: VecDeque<WsMessage>,
+}
+
+impl Iterator for Unsent<'_> {
+    type Item = WsMessage;
+
+    fn next(&mut self) -> Option<WsMessage> {
+        self.messages.pop_front()
+    }
+}
+
+impl Drop for Unsent<'_> {
+    fn drop(&mut self) {
+        // Never written:
+        let num_bytes = self.messages.iter().map(WsMessage::num_bytes).sum();
+        self.shared.written(num_bytes);
+    }
+}
+
+/// How a connection ended.
+pub(crate) enum Ended {
+    /// We closed the connection, because the [`WsSender`] was closed or dropped,
+    /// or because the event handler returned [`ControlFlow::Break`].
+    ByUs(Result<()>),
+
+    /// The connection failed to open, was closed by the server, or was lost.
+    Lost(Result<()>),
+}
+
+/// Reports [`WsEvent`]s to the event handler, and remembers if it wants us to stop.
+pub(crate) struct Events<E> {
+    on_event: E,
+
+    backoff: Backoff,
+
+    /// Set when the event handler returns [`ControlFlow::Break`].
+    stop: bool,
+}
+
+impl<E: Fn(WsEvent) -> ControlFlow<()>> Events<E> {
+    pub fn new(options: &Options, on_event: E) -> Self {
+        Self {
+            on_event,
+            backoff: Backoff::new(options),
+            stop: false,
+        }
+    }
+
+    pub fn emit(&mut self, event: WsEvent) {
+        self.stop |= (self.on_event)(event).is_break();
+    }
+
+    /// Has the event handler returned [`ControlFlow::Break`]?
+    pub fn stop(&self) -> bool {
+        self.stop
+    }
+
+    /// Call when the connection could not be opened.
+    pub fn failed(&mut self, err: Error) -> Ended {
+        self.emit(WsEvent::Error(err.clone()));
+        self.ended(false, Err(err))
+    }
+
+    /// Call when the connection has been opened.
+    pub fn opened(&mut self, info: ConnectionInfo) {
+        let event = self.backoff.opened(info);
+        self.emit(event);
+    }
+
+    /// Call with each message from the server.
+    ///
+    /// Returns how the server closed the connection, if it did.
+    /// Acknowledge that, and then call [`Self::closed`].
+    pub fn received(
+        &mut self,
+        liveness: &mut Liveness,
+        message: tungstenite::Message,
+    ) -> Option<CloseInfo> {
+        use tungstenite::Message;
+
+        if let Some(rtt) = liveness.received(matches!(message, Message::Pong(_))) {
+            self.emit(WsEvent::RoundTripTime(rtt));
+        }
+        match message {
+            Message::Text(text) => self.emit(WsEvent::Message(WsMessage::Text(text))),
+            Message::Binary(data) => self.emit(WsEvent::Message(WsMessage::Binary(data))),
+            Message::Ping(data) => self.emit(WsEvent::Message(WsMessage::Ping(data))),
+            Message::Pong(data) => self.emit(WsEvent::Message(WsMessage::Pong(data))),
+            Message::Close(close) => {
+                log::debug!("Close received: {close:?}");
+                return Some(close_info(close));
+            }
+            Message::Frame(_) => {}
+        }
+        None
+    }
+
+    /// Call when the server has closed the connection.
+    ///
+    /// `closing` is whether we had sent a close frame first.
+    pub fn closed(&mut self, info: CloseInfo, closing: bool) -> Ended {
+        self.emit(WsEvent::Closed(info));
+        self.ended(closing, Ok(()))
+    }
+
+    /// Report the error, and that the connection was lost.
+    ///
+    /// `closing` is whether we had sent a close frame first.
+    pub fn lost(&mut self, err: Error, closing: bool) -> Ended {
+        self.emit(WsEvent::Error(err.clone()));
+        self.emit(WsEvent::Closed(CloseInfo::abnormal()));
+        self.ended(closing, Err(err))
+    }
+
+    /// Call when the server has hung up without a close frame.
+    ///
+    /// `closing` is whether we had sent a close frame first.
+    pub fn hung_up(&mut self, closing: bool) -> Ended {
+        self.emit(WsEvent::Closed(CloseInfo::abnormal()));
+        self.ended(closing, Ok(()))
+    }
+
+    /// Call when the server has not acknowledged our close frame within [`CLOSE_TIMEOUT`].
+    pub fn close_timed_out(&mut self) -> Ended {
+        log::debug!("The server did not acknowledge our close frame in time.");
+        self.emit(WsEvent::Closed(CloseInfo::abnormal()));
+        Ended::ByUs(Ok(()))
+    }
+
+    /// Call when a connection has ended.
+    ///
+    /// Returns how long to wait before reconnecting,
+    /// or the result to give up with.
+    pub fn reconnect(&mut self, ended: Ended) -> ControlFlow<Result<()>, Duration> {
+        let result = match ended {
+            Ended::ByUs(result) => return ControlFlow::Break(result),
+            Ended::Lost(result) => result,
+        };
+        let Some((event, delay)) = self.backoff.next_attempt() else {
+            return ControlFlow::Break(result);
+        };
+        self.emit(event);
+        if self.stop {
+            ControlFlow::Break(result)
+        } else {
+            ControlFlow::Continue(delay)
+        }
+    }
+
+    fn ended(&self, closing: bool, result: Result<()>) -> Ended {
+        if closing || self.stop {
+            Ended::ByUs(result)
+        } else {
+            Ended::Lost(result)
+        }
+    }
+}
+
+/// Notices when an open connection has died,
+/// see [`Options::heartbeat`] and [`Options::idle_timeout`].
+pub(crate) struct Liveness {
+    start: Instant,
+
+    heartbeat: Option<Heartbeat>,
+
+    idle_timeout: Option<Duration>,
+
+    last_received: Instant,
+}
+
+impl Liveness {
+    /// Call when the connection opens.
+    pub fn new(options: &Options) -> Self {
+        let start = Instant::now();
+        Self {
+            start,
+            heartbeat: options.heartbeat.as_ref().map(Heartbeat::new),
+            idle_timeout: options.idle_timeout,
+            last_received: start,
+        }
+    }
+
+    /// When [`Self::poll`] should be called next, if ever.
+    pub fn next_wakeup(&self) -> Option<Instant> {
+        self.heartbeat
+            .as_ref()
+            .map(|heartbeat| self.start + heartbeat.next_wakeup())
+            .into_iter()
+            .chain(
+                self.idle_timeout
+                    .map(|timeout| self.last_received + timeout),
+            )
+            .min()
+    }
+
+    /// Call at [`Self::next_wakeup`], or whenever convenient.
+    ///
+    /// Returns the ping to send, if it is time for one.
+    /// Fails with an [`Error::Timeout`] if the connection seems dead.
+    pub fn poll(&mut self) -> Result<Option<tungstenite::Message>> {
+        if let Some(idle_timeout) = self.idle_timeout {
+            if idle_timeout <= self.last_received.elapsed() {
+                log::warn!("Nothing received for too long - closing connection.");
+                return Err(Error::Timeout(TimeoutKind::Idle));
+            }
+        }
+        let now = self.start.elapsed();
+        match self
+            .heartbeat
+            .as_mut()
+            .and_then(|heartbeat| heartbeat.poll(now))
+        {
+            Some(Beat::Ping) => Ok(Some(tungstenite::Message::Ping(vec![]))),
+            Some(Beat::TimedOut) => {
+                log::warn!("No reply to the heartbeat ping - closing connection.");
+                Err(Error::Timeout(TimeoutKind::Heartbeat))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Call when anything is received.
+    ///
+    /// Returns the round-trip time if it is the reply to our ping.
+    fn received(&mut self, is_pong: bool) -> Option<Duration> {
+        self.last_received = Instant::now();
+        let now = self.start.elapsed();
+        self.heartbeat
+            .as_mut()
+            .and_then(|heartbeat| heartbeat.received(now, is_pong))
+    }
+}
+
+/// A boxed future, as returned by an [`AsyncRuntime`].
+#[cfg(any(feature = "tokio", feature = "agnostic"))]
+pub(crate) type BoxFuture<'a, T> = Pin<Box<dyn Future<Output = T> + Send + 'a>>;
+
+/// What an async backend needs from its runtime, to run connections with [`ws_connect_async`].
+#[cfg(any(feature = "tokio", feature = "agnostic"))]
+pub(crate) trait AsyncRuntime: 'static {
+    /// A TCP connection.
+    type TcpStream: Send;
+
+    /// A WebSocket connection, with or without TLS.
+    type WebSocketStream: futures_sink::Sink<tungstenite::Message, Error = tungstenite::Error>
+        + futures_core::Stream<Item = std::result::Result<tungstenite::Message, tungstenite::Error>>
+        + Send
+        + Unpin;
+
+    /// The sending half of the channel from a [`WsSender`] to its connection.
+    type Sender: Channel + 'static;
+
+    /// The receiving half of the channel from a [`WsSender`] to its connection.
+    type Receiver: Send;
+
+    /// Finishes at a given time, see [`Self::sleep_until`].
+    type Sleep: Future + Send;
+
+    /// A channel with room for [`SEND_QUEUE_CAPACITY`] messages.
+    fn channel() -> (Self::Sender, Self::Receiver);
+
+    /// Wait for the next message, or `None` once the sending half has been dropped.
+    fn poll_recv(rx: &mut Self::Receiver, cx: &mut Context<'_>) -> Poll<Option<WsMessage>>;
+
+    /// The next message, if there is one already.
+    fn try_recv(rx: &mut Self::Receiver) -> Option<WsMessage>;
+
+    fn sleep_until(at: Instant) -> Self::Sleep;
+
+    /// Look up the addresses of the host.
+    fn resolve(host: &str, port: u16) -> BoxFuture<'_, std::io::Result<Vec<SocketAddr>>>;
+
+    /// Open a TCP connection to the address.
+    fn connect(addr: SocketAddr) -> BoxFuture<'static, std::io::Result<Self::TcpStream>>;
+
+    /// Establish TLS if `tls` is set, and upgrade the TCP `stream` to a WebSocket,
+    /// within the timeouts of the `options` (see [`Deadline::step`]).
+    fn handshake<'a>(
+        request: Request,
+        stream: Self::TcpStream,
+        host: &'a str,
+        tls: bool,
+        options: &'a Options,
+        open: Option<Instant>,
+    ) -> BoxFuture<'a, Result<(Self::WebSocketStream, Response)>>;
+}
+
+/// Wait for whichever future finishes first, preferring `a` if both are ready.
+#[cfg(any(feature = "tokio", feature = "agnostic"))]
+async fn first<T>(a: impl Future<Output = T>, b: impl Future<Output = T>) -> T {
+    use futures_util::future::{select, Either};
+
+    futures_util::pin_mut!(a, b);
+    match select(a, b).await {
+        Either::Left((output, _)) | Either::Right((output, _)) => output,
+    }
+}
+
+/// Run the future, or fail with an [`Error::Timeout`] if it does not finish before the deadline.
+#[cfg(any(feature = "tokio", feature = "agnostic"))]
+pub(crate) async fn within<R: AsyncRuntime, T>(
+    deadline: Option<Deadline>,
+    future: impl Future<Output = Result<T>>,
+) -> Result<T> {
+    match deadline {
+        Some(deadline) => {
+            first(future, async {
+                R::sleep_until(deadline.at).await;
+                Err(Error::Timeout(deadline.kind))
+            })
+            .await
+        }
+        None => future.await,
+    }
+}
+
+/// Connect, and return the stream together with the handshake response.
+#[cfg(any(feature = "tokio", feature = "agnostic"))]
+async fn connect_stream<R: AsyncRuntime>(
+    url: &str,
+    options: &Options,
+) -> Result<(R::WebSocketStream, Response)> {
+    let open = options.open_timeout.map(|timeout| Instant::now() + timeout);
+    let request = into_requester(url, options)?;
+    let (host, port) = host_and_port(&request)?;
+    let tls = is_tls(&request)?;
+
+    let deadline = Deadline::step(open, None, TimeoutKind::Open);
+    let addrs = within::<R, _>(deadline, async {
+        R::resolve(&host, port)
+            .await
+            .map_err(|err| Error::Dns(Arc::new(err)))
+    })
+    .await?;
+
+    let deadline = Deadline::step(open, options.connect_timeout, TimeoutKind::Connect);
+    let stream = within::<R, _>(deadline, connect_to_some::<R>(addrs, &host)).await?;
+
+    R::handshake(request, stream, &host, tls, options, open).await
+}
+
+/// Open a TCP connection to the first of the given addresses that accepts one.
+#[cfg(any(feature = "tokio", feature = "agnostic"))]
+async fn connect_to_some<R: AsyncRuntime>(
+    addrs: Vec<SocketAddr>,
+    host: &str,
+) -> Result<R::TcpStream> {
+    let mut last_err = None;
+    for addr in addrs {
+        match R::connect(addr).await {
+            Ok(stream) => return Ok(stream),
+            Err(err) => {
+                log::debug!("Failed to connect to {addr}: {err}");
+                last_err = Some(err);
+            }
+        }
+    }
+    Err(match last_err {
+        Some(err) => Error::Io(Arc::new(err)),
+        None => Error::Dns(Arc::new(std::io::Error::new(
+            std::io::ErrorKind::NotFound,
+            format!("No addresses found for {host:?}"),
+        ))),
+    })
+}
+
+/// Returns the sender, and the future that runs the connection
+/// (reconnecting if [`Options::reconnect`] is set) until it has ended for good.
+///
+/// The backend spawns the future on its runtime, or hands it to the user to spawn.
+#[cfg(any(feature = "tokio", feature = "agnostic"))]
+pub(crate) fn ws_connect_async<R: AsyncRuntime>(
+    url: String,
+    options: Options,
+    on_event: EventHandler,
+) -> (WsSender, impl Future<Output = ()> + Send + 'static) {
+    let (tx, mut rx) = R::channel();
+    let shared = Shared::new(&options);
+
+    let connection = {
+        let shared = shared.clone();
+        async move {
+            run_until_ended::<R>(&url, &options, &mut rx, &shared, on_event).await;
+            // Nothing more will be written:
+            shared.end(std::iter::from_fn(|| R::try_recv(&mut rx)));
+            log::debug!("WS connection finished.");
+        }
+    };
+    (WsSender::new(tx, shared), connection)
+}
+
+/// What woke up an async connection.
+#[cfg(any(feature = "tokio", feature = "agnostic"))]
+enum Wakeup {
+    /// A message from the [`WsSender`], or `None` if it has been closed.
+    Outgoing(Option<WsMessage>),
+
+    /// A message from the server, or `None` if the connection has ended.
+    Incoming(Option<std::result::Result<tungstenite::Message, tungstenite::Error>>),
+
+    /// It is time for the next heartbeat, or to give up on closing nicely.
+    Timer,
+}
+
+/// Connect, and reconnect whenever the connection is lost, until it ends for good.
+#[cfg(any(feature = "tokio", feature = "agnostic"))]
+async fn run_until_ended<R: AsyncRuntime>(
+    url: &str,
+    options: &Options,
+    rx: &mut R::Receiver,
+    shared: &Shared,
+    on_event: EventHandler,
+) {
+    let mut events = Events::new(options, on_event);
+
+    loop {
+        let ended = connect_and_run::<R>(url, options, rx, shared, &mut events).await;
+        let delay = match events.reconnect(ended) {
+            ControlFlow::Continue(delay) => delay,
+            ControlFlow::Break(_) => return,
+        };
+
+        // Anything not yet written has to wait for the next connection:
+        shared.close(std::iter::from_fn(|| R::try_recv(rx)));
+
+        // Wait before reconnecting, but stop if the sender is dropped in the meantime:
+        let reconnect_time = Instant::now() + delay;
+        loop {
+            let outgoing = async { Wakeup::Outgoing(poll_fn(|cx| R::poll_recv(rx, cx)).await) };
+            let timer = async {
+                R::sleep_until(reconnect_time).await;
+                Wakeup::Timer
+            };
+            match first(outgoing, timer).await {
+                Wakeup::Outgoing(Some(message)) => shared.requeue(message),
+                Wakeup::Outgoing(None) => {
+                    log::debug!("WsSender dropped while waiting to reconnect.");
+                    return;
+                }
+                Wakeup::Incoming(_) | Wakeup::Timer => break,
+            }
+        }
+    }
+}
+
+/// Send a ping if it is time to, and fail if the server has stopped responding.
+#[cfg(any(feature = "tokio", feature = "agnostic"))]
+async fn beat<R: AsyncRuntime>(
+    liveness: &mut Liveness,
+    ws_stream: &mut R::WebSocketStream,
+) -> Result<()> {
+    use futures_util::SinkExt as _;
+
+    if let Some(ping) = liveness.poll()? {
+        ws_stream.send(ping).await?;
+    }
+    Ok(())
+}
+
+/// Connect, send the messages in the outbox, and then handle the connection until it ends.
+#[cfg(any(feature = "tokio", feature = "agnostic"))]
+async fn connect_and_run<R: AsyncRuntime>(
+    url: &str,
+    options: &Options,
+    rx: &mut R::Receiver,
+    shared: &Shared,
+    // Not `&EventHandler`, which would make the future `!Send`, since the handler is not `Sync`:
+    events: &mut Events<EventHandler>,
+) -> Ended {
+    use futures_util::{SinkExt as _, StreamExt as _};
+
+    let (mut ws_stream, response) = match connect_stream::<R>(url, options).await {
+        Ok(result) => result,
+        Err(err) => return events.failed(err),
+    };
+
+    let info = match connection_info(options, &response) {
+        Ok(info) => info,
+        Err(err) => {
+            ws_stream.send(tungstenite::Message::Close(None)).await.ok();
+            return events.failed(err);
+        }
+    };
+
+    log::info!("WebSocket handshake has been successfully completed");
+    events.opened(info);
+
+    // From now on new messages go straight to `rx`, after the buffered ones:
+    for message in shared.open(events) {
+        let num_bytes = message.num_bytes();
+        let result = ws_stream.send(into_tungstenite_message(message)).await;
+        shared.written(num_bytes);
+        if let Err(err) = result {
+            return events.lost(err.into(), false);
+        }
+    }
+
+    let mut liveness = Liveness::new(options);
+
+    // Set when we have sent a close frame, and are waiting for the server to acknowledge it.
+    let mut close_deadline: Option<Instant> = None;
+
+    loop {
+        if events.stop() && close_deadline.is_none() {
+            log::debug!("Event handler returned Break - closing connection.");
+            let close = tungstenite::Message::Close(Some(normal_close_frame()));
+            ws_stream.send(close).await.ok();
+            close_deadline = Some(Instant::now() + CLOSE_TIMEOUT);
+        }
+
+        let timer_deadline = close_deadline.or_else(|| liveness.next_wakeup());
+
+        let outgoing = async {
+            if close_deadline.is_some() {
+                // Nothing more is sent after the close frame.
+                std::future::pending().await
+            } else {
+                Wakeup::Outgoing(poll_fn(|cx| R::poll_recv(rx, cx)).await)
+            }
+        };
+        let incoming = async { Wakeup::Incoming(ws_stream.next().await) };
+        let timer = async {
+            match timer_deadline {
+                Some(deadline) => {
+                    R::sleep_until(deadline).await;
+                }
+                None => std::future::pending().await,
+            }
+            Wakeup::Timer
+        };
+
+        match first(outgoing, first(incoming, timer)).await {
+            Wakeup::Outgoing(Some(message)) => {
+                shared.dequeue();
+                let num_bytes = message.num_bytes();
+                let result = ws_stream.send(into_tungstenite_message(message)).await;
+                shared.written(num_bytes);
+                if let Err(err) = result {
+                    return events.lost(err.into(), false);
+                }
+            }
+
+            Wakeup::Outgoing(None) => {
+                log::debug!("WsSender dropped - closing connection.");
+                let close = tungstenite::Message::Close(shared.take_close_frame());
+                ws_stream.send(close).await.ok();
+                close_deadline = Some(Instant::now() + CLOSE_TIMEOUT);
+            }
+
+            Wakeup::Timer if close_deadline.is_some() => {
+                return events.close_timed_out();
+            }
+
+            Wakeup::Timer => {
+                if let Err(err) = beat::<R>(&mut liveness, &mut ws_stream).await {
+                    return events.lost(err, false);
+                }
+            }
+
+            Wakeup::Incoming(Some(Ok(message))) => {
+                if let Some(close) = events.received(&mut liveness, message) {
+                    // Acknowledge, if the server initiated the close:
+                    ws_stream.flush().await.ok();
+                    return events.closed(close, close_deadline.is_some());
+                }
+            }
+
+            Wakeup::Incoming(Some(Err(err))) => {
+                return events.lost(err.into(), close_deadline.is_some());
+            }
+
+            Wakeup::Incoming(None) => return events.hung_up(close_deadline.is_some()),
+        }
+    }
+}
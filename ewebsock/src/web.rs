@@ -1,10 +1,31 @@
-use crate::{EventHandler, Result, WsEvent, WsMessage};
+use std::{
+    cell::{Cell, RefCell},
+    pin::Pin,
+    rc::Rc,
+    task::{Context, Poll, Waker},
+    time::Duration,
+};
+
+use wasm_bindgen::{closure::Closure, prelude::wasm_bindgen, JsCast as _};
+
+use crate::{
+    heartbeat::{Beat, Heartbeat},
+    outbox::Outbox,
+    reconnect::Backoff,
+    CloseInfo, ConnectionInfo, Error, EventHandler, HeartbeatOptions, Options, Result, TimeoutKind,
+    TlsOptions, WsEvent, WsMessage,
+};
 
 #[allow(clippy::needless_pass_by_value)]
 fn string_from_js_value(s: wasm_bindgen::JsValue) -> String {
     s.as_string().unwrap_or(format!("{:#?}", s))
 }
 
+#[allow(clippy::needless_pass_by_value)]
+fn error_from_js_value(s: wasm_bindgen::JsValue) -> Error {
+    Error::Js(string_from_js_value(s))
+}
+
 #[allow(clippy::needless_pass_by_value)]
 fn string_from_js_string(s: js_sys::JsString) -> String {
     s.as_string().unwrap_or(format!("{:#?}", s))
@@ -12,150 +33,776 @@ fn string_from_js_string(s: js_sys::JsString) -> String {
 
 /// This is how you send messages to the server.
 ///
-/// When this is dropped, the connection is closed.
+/// This can be cloned (but not shared between threads).
+/// When the last clone of this is dropped, the connection is closed.
+#[derive(Clone)]
 pub struct WsSender {
-    ws: Option<web_sys::WebSocket>,
+    inner: Rc<Inner>,
 }
 
-impl Drop for WsSender {
+/// The state shared by all clones of a [`WsSender`].
+struct Inner {
+    /// Taken when the connection is closed.
+    shared: RefCell<Option<Rc<Shared>>>,
+}
+
+impl Drop for Inner {
     fn drop(&mut self) {
-        if let Err(err) = self.close() {
-            log::warn!("Failed to close WebSocket: {err:?}");
+        if let Some(ws) = self.stop() {
+            log::debug!("Closing WebSocket");
+            if let Err(err) = ws.close().map_err(error_from_js_value) {
+                log::warn!("Failed to close WebSocket: {err:?}");
+            }
         }
     }
 }
 
+impl Inner {
+    /// Stop reconnecting, and return the current connection so it can be closed.
+    fn stop(&self) -> Option<web_sys::WebSocket> {
+        let shared = self.shared.borrow_mut().take();
+        shared.and_then(|shared| shared.stop())
+    }
+}
+
 impl WsSender {
     /// Send the message to the server.
-    pub fn send(&mut self, msg: WsMessage) {
-        if let Some(ws) = &mut self.ws {
-            let result = match msg {
-                WsMessage::Binary(data) => {
-                    ws.set_binary_type(web_sys::BinaryType::Blob);
-                    ws.send_with_u8_array(&data)
-                }
-                WsMessage::Text(text) => ws.send_with_str(&text),
-                unknown => {
-                    panic!("Don't know how to send message: {:?}", unknown);
-                }
-            };
-            if let Err(err) = result.map_err(string_from_js_value) {
-                log::error!("Failed to send: {:?}", err);
-            }
+    ///
+    /// Messages sent before [`WsEvent::Opened`], or while reconnecting,
+    /// are buffered and sent once the connection opens. See [`Options::outbox`].
+    ///
+    /// Errors are logged. Use [`Self::try_send`] to handle them yourself.
+    pub fn send(&self, msg: WsMessage) {
+        if let Err(err) = self.try_send(msg) {
+            log::error!("Failed to send: {err}");
+        }
+    }
+
+    /// Send a message, or fail if it cannot be.
+    ///
+    /// # Errors
+    /// * [`Error::Closed`] if the connection is closed, and will not be reconnected.
+    /// * [`Error::OutboxFull`] if the connection is not open,
+    ///   and the outbox is full (see [`crate::OverflowPolicy::Error`]).
+    /// * [`Error::InvalidMessage`] unless it is a [`WsMessage::Text`] or [`WsMessage::Binary`],
+    ///   since the browser cannot send anything else.
+    /// * [`Error::Js`] if the browser refuses to send the message.
+    pub fn try_send(&self, msg: WsMessage) -> Result<()> {
+        if !matches!(msg, WsMessage::Text(_) | WsMessage::Binary(_)) {
+            return Err(Error::InvalidMessage(format!(
+                "Only text and binary messages can be sent on web, not {msg:?}"
+            )));
         }
+        let Some(shared) = self.shared() else {
+            return Err(Error::Closed);
+        };
+        if !shared.outbox.borrow().is_open() {
+            return shared.outbox.borrow_mut().push(msg);
+        }
+        match &*shared.ws.borrow() {
+            // The browser silently drops messages sent while the connection is closing.
+            Some(ws) if ws.ready_state() == web_sys::WebSocket::OPEN => send_message(ws, msg),
+            _ => Err(Error::Closed),
+        }
+    }
+
+    /// The number of bytes that have been sent, but not yet written to the connection.
+    ///
+    /// This is the `bufferedAmount` of the browser `WebSocket`,
+    /// plus the messages waiting in the outbox.
+    /// Use this to slow down if the connection cannot keep up.
+    pub fn buffered_amount(&self) -> usize {
+        let Some(shared) = self.shared() else {
+            return 0;
+        };
+        let in_browser = shared
+            .ws
+            .borrow()
+            .as_ref()
+            .map_or(0, |ws| ws.buffered_amount() as usize);
+        shared.outbox.borrow().num_bytes() + in_browser
     }
 
-    /// Close the conenction.
+    /// Close the connection.
     ///
-    /// This is called automatically when the sender is dropped.
-    pub fn close(&mut self) -> Result<()> {
-        if let Some(ws) = self.ws.take() {
+    /// This closes it for all clones of this sender.
+    /// It is called automatically when the last clone is dropped.
+    pub fn close(&self) -> Result<()> {
+        if let Some(ws) = self.inner.stop() {
             log::debug!("Closing WebSocket");
-            ws.close().map_err(string_from_js_value)
+            ws.close().map_err(error_from_js_value)
+        } else {
+            Ok(())
+        }
+    }
+
+    /// Close the connection with the given close code and reason.
+    ///
+    /// The code must be `1000` (normal closure) or in the range `3000`–`4999`,
+    /// and the reason must be at most 123 bytes long.
+    ///
+    /// The server acknowledges the close with a [`WsEvent::Closed`].
+    pub fn close_with(&self, code: u16, reason: impl Into<String>) -> Result<()> {
+        let reason = reason.into();
+        crate::check_close_frame(code, &reason)?;
+        if let Some(ws) = self.inner.stop() {
+            log::debug!("Closing WebSocket with code {code}");
+            ws.close_with_code_and_reason(code, &reason)
+                .map_err(error_from_js_value)
         } else {
             Ok(())
         }
     }
 
     /// Forget about this sender without closing the connection.
-    pub fn forget(mut self) {
-        self.ws = None;
-    }
-}
-
-pub(crate) fn ws_receive_impl(url: String, on_event: EventHandler) -> Result<()> {
-    ws_connect_impl(url, on_event).map(|sender| sender.forget())
-}
-
-pub(crate) fn ws_connect_impl(url: String, on_event: EventHandler) -> Result<WsSender> {
-    // Based on https://rustwasm.github.io/wasm-bindgen/examples/websockets.html
-
-    use wasm_bindgen::closure::Closure;
-    use wasm_bindgen::JsCast as _;
-
-    // Connect to an server
-    let ws = web_sys::WebSocket::new(&url).map_err(string_from_js_value)?;
-
-    // For small binary messages, like CBOR, Arraybuffer is more efficient than Blob handling
-    ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
-
-    // Allow it to be shared by the different callbacks:
-    let on_event: std::rc::Rc<dyn Send + Fn(WsEvent) -> std::ops::ControlFlow<()>> =
-        on_event.into();
-
-    // onmessage callback
-    {
-        let on_event = on_event.clone();
-        let onmessage_callback = Closure::wrap(Box::new(move |e: web_sys::MessageEvent| {
-            // Handle difference Text/Binary,...
-            if let Ok(abuf) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
-                let array = js_sys::Uint8Array::new(&abuf);
-                on_event(WsEvent::Message(WsMessage::Binary(array.to_vec())));
-            } else if let Ok(blob) = e.data().dyn_into::<web_sys::Blob>() {
-                // better alternative to juggling with FileReader is to use https://crates.io/crates/gloo-file
-                let file_reader = web_sys::FileReader::new().expect("Failed to create FileReader");
-                let file_reader_clone = file_reader.clone();
-                // create onLoadEnd callback
-                let on_event = on_event.clone();
-                let onloadend_cb = Closure::wrap(Box::new(move |_e: web_sys::ProgressEvent| {
-                    let array = js_sys::Uint8Array::new(&file_reader_clone.result().unwrap());
-                    on_event(WsEvent::Message(WsMessage::Binary(array.to_vec())));
-                })
-                    as Box<dyn FnMut(web_sys::ProgressEvent)>);
-                file_reader.set_onloadend(Some(onloadend_cb.as_ref().unchecked_ref()));
-                file_reader
-                    .read_as_array_buffer(&blob)
-                    .expect("blob not readable");
-                onloadend_cb.forget();
-            } else if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
-                on_event(WsEvent::Message(WsMessage::Text(string_from_js_string(
-                    txt,
-                ))));
-            } else {
-                log::debug!("Unknown websocket message received: {:?}", e.data());
-                on_event(WsEvent::Message(WsMessage::Unknown(string_from_js_value(
-                    e.data(),
-                ))));
+    ///
+    /// The connection can still be closed with [`Self::close`] on another clone.
+    pub fn forget(self) {
+        std::mem::forget(self);
+    }
+
+    fn shared(&self) -> Option<Rc<Shared>> {
+        self.inner.shared.borrow().clone()
+    }
+}
+
+/// How many bytes may wait to be sent before [`WsSender`] stops being ready as a sink.
+const SINK_HIGH_WATER_MARK: usize = 1024 * 1024;
+
+/// How often to check if the browser has sent the buffered bytes.
+///
+/// The browser does not tell us when `bufferedAmount` goes down.
+const SINK_POLL_INTERVAL: Duration = Duration::from_millis(10);
+
+/// Sends the messages like [`WsSender::try_send`].
+///
+/// `poll_ready` waits until the connection is open, and fewer than 1 MiB are waiting to be sent,
+/// so a sink never fills up the outbox.
+/// `poll_flush` waits until nothing is waiting to be sent, including the messages in the outbox,
+/// and `poll_close` flushes before it closes the connection.
+impl futures_sink::Sink<WsMessage> for WsSender {
+    type Error = Error;
+
+    fn poll_ready(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        let Some(shared) = self.shared() else {
+            return Poll::Ready(Err(Error::Closed));
+        };
+        let outbox = shared.outbox.borrow();
+        if outbox.is_ended() {
+            return Poll::Ready(Err(Error::Closed));
+        }
+        if outbox.is_open() && self.buffered_amount() < SINK_HIGH_WATER_MARK {
+            Poll::Ready(Ok(()))
+        } else {
+            wake_later(cx.waker());
+            Poll::Pending
+        }
+    }
+
+    fn start_send(self: Pin<&mut Self>, msg: WsMessage) -> Result<()> {
+        self.try_send(msg)
+    }
+
+    fn poll_flush(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.buffered_amount() == 0 {
+            Poll::Ready(Ok(()))
+        } else {
+            wake_later(cx.waker());
+            Poll::Pending
+        }
+    }
+
+    fn poll_close(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        std::task::ready!(self.as_mut().poll_flush(cx))?;
+        Poll::Ready(self.close())
+    }
+}
+
+/// Wake up the task after [`SINK_POLL_INTERVAL`].
+fn wake_later(waker: &Waker) {
+    let waker = waker.clone();
+    let callback = Closure::once_into_js(move || waker.wake());
+    set_timeout(callback.unchecked_ref(), millis(SINK_POLL_INTERVAL));
+}
+
+/// The duration in whole milliseconds, for `setTimeout`.
+fn millis(duration: Duration) -> i32 {
+    duration.as_millis().min(i32::MAX as u128) as i32
+}
+
+fn send_message(ws: &web_sys::WebSocket, msg: WsMessage) -> Result<()> {
+    match msg {
+        WsMessage::Binary(data) => {
+            ws.set_binary_type(web_sys::BinaryType::Blob);
+            ws.send_with_u8_array(&data)
+        }
+        WsMessage::Text(text) => ws.send_with_str(&text),
+        WsMessage::Unknown(_) | WsMessage::Ping(_) | WsMessage::Pong(_) => {
+            unreachable!("Rejected by WsSender::try_send, and check_options for heartbeats")
+        }
+    }
+    .map_err(error_from_js_value)
+}
+
+#[wasm_bindgen]
+extern "C" {
+    #[wasm_bindgen(js_name = setTimeout)]
+    fn set_timeout(handler: &js_sys::Function, timeout: i32) -> i32;
+
+    #[wasm_bindgen(js_name = clearTimeout)]
+    fn clear_timeout(handle: i32);
+}
+
+/// State shared by the [`WsSender`] and the callbacks of the current `WebSocket`.
+struct Shared {
+    url: String,
+    subprotocols: Vec<String>,
+    on_event: EventHandler,
+
+    /// See [`Options::max_message_size`].
+    max_message_size: Option<usize>,
+
+    /// The current connection, if any.
+    ws: RefCell<Option<web_sys::WebSocket>>,
+
+    /// The event handlers of the most recent connection, until it has closed.
+    ///
+    /// These hold on to the [`Shared`], so they must be dropped for the event handler to be.
+    callbacks: RefCell<Option<Callbacks>>,
+
+    backoff: RefCell<Backoff>,
+
+    /// Messages sent while the connection is not open.
+    outbox: RefCell<Outbox>,
+
+    /// The `setTimeout` handle of the scheduled reconnect, if any.
+    timer: Cell<Option<i32>>,
+
+    /// See [`Options::open_timeout`].
+    open_timeout: Option<Duration>,
+
+    /// The `setTimeout` handle of the open timeout of the current connection, if any.
+    open_timer: Cell<Option<i32>>,
+
+    /// See [`Options::idle_timeout`].
+    idle_timeout: Option<Duration>,
+
+    /// The `setTimeout` handle of the idle timeout of the current connection, if any.
+    idle_timer: Cell<Option<i32>>,
+
+    heartbeat_options: Option<HeartbeatOptions>,
+
+    /// The heartbeat of the current connection, if open.
+    heartbeat: RefCell<Option<Heartbeat>>,
+
+    /// The `setTimeout` handle of the next heartbeat, if any.
+    heartbeat_timer: Cell<Option<i32>>,
+
+    /// When the current connection was opened, from `Date.now()`.
+    opened_at: Cell<f64>,
+
+    /// Set when the [`WsSender`] is closed or dropped,
+    /// or when the event handler returns [`std::ops::ControlFlow::Break`].
+    stopped: Cell<bool>,
+}
+
+/// The event handlers set on a `WebSocket`.
+///
+/// Dropping this removes them from the `WebSocket`, and frees them.
+struct Callbacks {
+    ws: web_sys::WebSocket,
+    _onmessage: Closure<dyn FnMut(web_sys::MessageEvent)>,
+    _onerror: Closure<dyn FnMut(web_sys::ErrorEvent)>,
+    _onopen: Closure<dyn FnMut(wasm_bindgen::JsValue)>,
+    _onclose: Closure<dyn FnMut(web_sys::CloseEvent)>,
+}
+
+impl Drop for Callbacks {
+    fn drop(&mut self) {
+        self.ws.set_onmessage(None);
+        self.ws.set_onerror(None);
+        self.ws.set_onopen(None);
+        self.ws.set_onclose(None);
+    }
+}
+
+impl Shared {
+    /// Pass the event to the event handler,
+    /// and close the connection if it returns [`std::ops::ControlFlow::Break`].
+    fn emit(&self, event: WsEvent) {
+        if (self.on_event)(event).is_break() && !self.stopped.get() {
+            log::debug!("Event handler returned Break - closing connection.");
+            if let Some(ws) = self.stop() {
+                if let Err(err) = ws.close_with_code(1000).map_err(error_from_js_value) {
+                    log::warn!("Failed to close WebSocket: {err}");
+                }
+            }
+        }
+    }
+
+    /// Handle an incoming message.
+    fn received(self: &Rc<Self>, message: WsMessage) {
+        if let Some(max_size) = self.max_message_size {
+            let size = message.num_bytes();
+            if max_size < size {
+                log::warn!("Received a message of {size} bytes, larger than the limit of {max_size} bytes - closing connection.");
+                self.stop_heartbeat();
+                self.stop_idle_timeout();
+                self.emit(WsEvent::Error(Error::MessageTooLarge { size, max_size }));
+                self.abandon();
+                return;
             }
-        }) as Box<dyn FnMut(web_sys::MessageEvent)>);
+        }
+        self.schedule_idle_timeout();
+        let rtt = self.heartbeat.borrow_mut().as_mut().and_then(|heartbeat| {
+            let is_pong = message == heartbeat.options().web_pong;
+            heartbeat.received(self.since_opened(), is_pong)
+        });
+        if let Some(rtt) = rtt {
+            self.emit(WsEvent::RoundTripTime(rtt));
+        }
+        self.emit(WsEvent::Message(message));
+    }
+
+    /// Stop reconnecting, and return the current connection so it can be closed.
+    fn stop(&self) -> Option<web_sys::WebSocket> {
+        self.stopped.set(true);
+        self.outbox.borrow_mut().end();
+        if let Some(timer) = self.timer.take() {
+            clear_timeout(timer);
+        }
+        self.stop_open_timeout();
+        self.stop_idle_timeout();
+        self.stop_heartbeat();
+        self.ws.borrow_mut().take()
+    }
+
+    /// How long the current connection has been open.
+    fn since_opened(&self) -> Duration {
+        Duration::from_secs_f64((js_sys::Date::now() - self.opened_at.get()).max(0.0) / 1000.0)
+    }
+
+    /// Call when the connection opens.
+    fn start_heartbeat(self: &Rc<Self>) {
+        self.opened_at.set(js_sys::Date::now());
+        *self.heartbeat.borrow_mut() = self.heartbeat_options.as_ref().map(Heartbeat::new);
+        self.schedule_heartbeat();
+    }
+
+    fn stop_heartbeat(&self) {
+        self.heartbeat.borrow_mut().take();
+        if let Some(timer) = self.heartbeat_timer.take() {
+            clear_timeout(timer);
+        }
+    }
+
+    fn schedule_heartbeat(self: &Rc<Self>) {
+        let Some(wakeup) = self.heartbeat.borrow().as_ref().map(Heartbeat::next_wakeup) else {
+            return;
+        };
+        let shared = Rc::downgrade(self);
+        let callback = Closure::once_into_js(move || {
+            let Some(shared) = shared.upgrade() else {
+                return;
+            };
+            shared.heartbeat_timer.set(None);
+            shared.beat();
+        });
+        let delay = wakeup.saturating_sub(self.since_opened());
+        self.heartbeat_timer
+            .set(Some(set_timeout(callback.unchecked_ref(), millis(delay))));
+    }
+
+    /// Send a ping if it is time to, and close the connection if the server has stopped responding.
+    fn beat(self: &Rc<Self>) {
+        let now = self.since_opened();
+        let beat = self
+            .heartbeat
+            .borrow_mut()
+            .as_mut()
+            .and_then(|heartbeat| heartbeat.poll(now));
+        match beat {
+            Some(Beat::Ping) => {
+                let ping = self
+                    .heartbeat
+                    .borrow()
+                    .as_ref()
+                    .map(|heartbeat| heartbeat.options().web_ping.clone());
+                if let (Some(ws), Some(ping)) = (&*self.ws.borrow(), ping) {
+                    if let Err(err) = send_message(ws, ping) {
+                        log::warn!("Failed to send heartbeat ping: {err}");
+                    }
+                }
+            }
+            Some(Beat::TimedOut) => {
+                log::warn!("No reply to the heartbeat ping - closing connection.");
+                self.stop_heartbeat();
+                self.stop_idle_timeout();
+                self.emit(WsEvent::Error(Error::Timeout(TimeoutKind::Heartbeat)));
+                self.abandon();
+                return;
+            }
+            None => {}
+        }
+        self.schedule_heartbeat();
+    }
+
+    /// Give up on the current connection if it has not opened in time.
+    fn schedule_open_timeout(self: &Rc<Self>) {
+        let Some(open_timeout) = self.open_timeout else {
+            return;
+        };
+        let shared = Rc::downgrade(self);
+        let callback = Closure::once_into_js(move || {
+            let Some(shared) = shared.upgrade() else {
+                return;
+            };
+            shared.open_timer.set(None);
+            log::warn!("The connection did not open in time - closing it.");
+            shared.emit(WsEvent::Error(Error::Timeout(TimeoutKind::Open)));
+            shared.abandon();
+        });
+        self.open_timer.set(Some(set_timeout(
+            callback.unchecked_ref(),
+            millis(open_timeout),
+        )));
+    }
+
+    fn stop_open_timeout(&self) {
+        if let Some(timer) = self.open_timer.take() {
+            clear_timeout(timer);
+        }
+    }
+
+    /// Close the current connection if nothing more is received in time.
+    ///
+    /// Call when the connection opens, and on each message.
+    fn schedule_idle_timeout(self: &Rc<Self>) {
+        self.stop_idle_timeout();
+        let Some(idle_timeout) = self.idle_timeout.filter(|_| !self.stopped.get()) else {
+            return;
+        };
+        let shared = Rc::downgrade(self);
+        let callback = Closure::once_into_js(move || {
+            let Some(shared) = shared.upgrade() else {
+                return;
+            };
+            shared.idle_timer.set(None);
+            log::warn!("Nothing received for too long - closing connection.");
+            shared.stop_heartbeat();
+            shared.emit(WsEvent::Error(Error::Timeout(TimeoutKind::Idle)));
+            shared.abandon();
+        });
+        self.idle_timer.set(Some(set_timeout(
+            callback.unchecked_ref(),
+            millis(idle_timeout),
+        )));
+    }
 
-        // set message event handler on WebSocket
-        ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
+    fn stop_idle_timeout(&self) {
+        if let Some(timer) = self.idle_timer.take() {
+            clear_timeout(timer);
+        }
+    }
 
-        // forget the callback to keep it alive
-        onmessage_callback.forget();
+    /// Close the current connection without waiting for the browser, and reconnect if enabled.
+    fn abandon(self: &Rc<Self>) {
+        let ws = self.ws.borrow_mut().take();
+        if let Some(ws) = ws {
+            // Don't wait for the browser to notice that the connection is dead:
+            self.release_callbacks();
+            ws.close().ok();
+            self.emit(WsEvent::Closed(CloseInfo::abnormal()));
+            self.reconnect_later();
+        }
     }
 
-    {
-        let on_event = on_event.clone();
-        let onerror_callback = Closure::wrap(Box::new(move |error_event: web_sys::ErrorEvent| {
-            log::error!(
-                "error event: {}: {:?}",
-                error_event.message(),
-                error_event.error()
-            );
-            on_event(WsEvent::Error(error_event.message()));
-        }) as Box<dyn FnMut(web_sys::ErrorEvent)>);
-        ws.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
-        onerror_callback.forget();
+    /// Remove the event handlers from the most recent connection, and drop them.
+    fn release_callbacks(&self) {
+        let callbacks = self.callbacks.borrow_mut().take();
+        drop(callbacks);
     }
 
-    {
-        let on_event = on_event.clone();
-        let onopen_callback = Closure::wrap(Box::new(move |_| {
-            on_event(WsEvent::Opened);
-        }) as Box<dyn FnMut(wasm_bindgen::JsValue)>);
-        ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
-        onopen_callback.forget();
+    /// Call when the connection failed or was lost.
+    fn reconnect_later(self: &Rc<Self>) {
+        if self.stopped.get() {
+            return;
+        }
+        self.ws.borrow_mut().take();
+        self.outbox.borrow_mut().close();
+
+        let Some((event, delay)) = self.backoff.borrow_mut().next_attempt() else {
+            self.outbox.borrow_mut().end();
+            return;
+        };
+        self.emit(event);
+
+        // The sender keeps the `Shared` alive until it is stopped:
+        let shared = Rc::downgrade(self);
+        let callback = Closure::once_into_js(move || {
+            let Some(shared) = shared.upgrade() else {
+                return;
+            };
+            shared.timer.set(None);
+            if shared.stopped.get() {
+                return;
+            }
+            if let Err(err) = shared.connect() {
+                shared.emit(WsEvent::Error(err));
+                shared.reconnect_later();
+            }
+        });
+        self.timer
+            .set(Some(set_timeout(callback.unchecked_ref(), millis(delay))));
     }
 
-    {
-        let onclose_callback = Closure::wrap(Box::new(move |_| {
-            on_event(WsEvent::Closed);
-        }) as Box<dyn FnMut(wasm_bindgen::JsValue)>);
-        ws.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
-        onclose_callback.forget();
+    fn connect(self: &Rc<Self>) -> Result<()> {
+        // Based on https://rustwasm.github.io/wasm-bindgen/examples/websockets.html
+
+        // Each attempt gets new event handlers, so free those of the previous one:
+        self.release_callbacks();
+
+        // Connect to an server
+        let ws = if self.subprotocols.is_empty() {
+            web_sys::WebSocket::new(&self.url)
+        } else {
+            let subprotocols: js_sys::Array = self
+                .subprotocols
+                .iter()
+                .map(|protocol| wasm_bindgen::JsValue::from_str(protocol))
+                .collect();
+            web_sys::WebSocket::new_with_str_sequence(&self.url, &subprotocols)
+        }
+        .map_err(error_from_js_value)?;
+
+        // For small binary messages, like CBOR, Arraybuffer is more efficient than Blob handling
+        ws.set_binary_type(web_sys::BinaryType::Arraybuffer);
+
+        // onmessage callback
+        let onmessage = {
+            let shared = self.clone();
+            let onmessage_callback = Closure::wrap(Box::new(move |e: web_sys::MessageEvent| {
+                // Handle difference Text/Binary,...
+                if let Ok(abuf) = e.data().dyn_into::<js_sys::ArrayBuffer>() {
+                    let array = js_sys::Uint8Array::new(&abuf);
+                    shared.received(WsMessage::Binary(array.to_vec()));
+                } else if let Ok(blob) = e.data().dyn_into::<web_sys::Blob>() {
+                    // better alternative to juggling with FileReader is to use https://crates.io/crates/gloo-file
+                    let file_reader =
+                        web_sys::FileReader::new().expect("Failed to create FileReader");
+                    let file_reader_clone = file_reader.clone();
+                    // create onLoadEnd callback, which is freed once called
+                    let shared = Rc::downgrade(&shared);
+                    let onloadend_cb = Closure::once_into_js(move |_e: web_sys::ProgressEvent| {
+                        let Some(shared) = shared.upgrade() else {
+                            return;
+                        };
+                        let array = js_sys::Uint8Array::new(&file_reader_clone.result().unwrap());
+                        shared.received(WsMessage::Binary(array.to_vec()));
+                    });
+                    file_reader.set_onloadend(Some(onloadend_cb.unchecked_ref()));
+                    file_reader
+                        .read_as_array_buffer(&blob)
+                        .expect("blob not readable");
+                } else if let Ok(txt) = e.data().dyn_into::<js_sys::JsString>() {
+                    shared.received(WsMessage::Text(string_from_js_string(txt)));
+                } else {
+                    log::debug!("Unknown websocket message received: {:?}", e.data());
+                    shared.received(WsMessage::Unknown(string_from_js_value(e.data())));
+                }
+            })
+                as Box<dyn FnMut(web_sys::MessageEvent)>);
+
+            // set message event handler on WebSocket
+            ws.set_onmessage(Some(onmessage_callback.as_ref().unchecked_ref()));
+            onmessage_callback
+        };
+
+        let onerror = {
+            let shared = self.clone();
+            let onerror_callback =
+                Closure::wrap(Box::new(move |error_event: web_sys::ErrorEvent| {
+                    log::error!(
+                        "error event: {}: {:?}",
+                        error_event.message(),
+                        error_event.error()
+                    );
+                    shared.emit(WsEvent::Error(Error::Js(error_event.message())));
+                }) as Box<dyn FnMut(web_sys::ErrorEvent)>);
+            ws.set_onerror(Some(onerror_callback.as_ref().unchecked_ref()));
+            onerror_callback
+        };
+
+        let onopen = {
+            let shared = self.clone();
+            let ws = ws.clone();
+            let onopen_callback = Closure::wrap(Box::new(move |_| {
+                shared.stop_open_timeout();
+                // The browser fails the connection if the server picks a protocol we did not offer.
+                let protocol = ws.protocol();
+                let extensions = ws.extensions();
+                let event = shared.backoff.borrow_mut().opened(ConnectionInfo {
+                    status: None,
+                    headers: vec![],
+                    protocol: (!protocol.is_empty()).then_some(protocol),
+                    extensions: (!extensions.is_empty()).then_some(extensions),
+                });
+                shared.emit(event);
+                if shared.stopped.get() {
+                    return;
+                }
+
+                let (dropped, pending) = shared.outbox.borrow_mut().open();
+                if 0 < dropped {
+                    shared.emit(WsEvent::OutgoingDropped { count: dropped });
+                }
+                for message in pending {
+                    if let Err(err) = send_message(&ws, message) {
+                        log::error!("Failed to send: {err}");
+                    }
+                }
+                shared.start_heartbeat();
+                shared.schedule_idle_timeout();
+            })
+                as Box<dyn FnMut(wasm_bindgen::JsValue)>);
+            ws.set_onopen(Some(onopen_callback.as_ref().unchecked_ref()));
+            onopen_callback
+        };
+
+        let onclose = {
+            let shared = self.clone();
+            let onclose_callback =
+                Closure::wrap(Box::new(move |close_event: web_sys::CloseEvent| {
+                    shared.stop_open_timeout();
+                    shared.stop_idle_timeout();
+                    shared.stop_heartbeat();
+                    // Nothing more will happen on this connection.
+                    // wasm-bindgen waits for this call to return before freeing the closure.
+                    shared.release_callbacks();
+                    shared.emit(WsEvent::Closed(CloseInfo {
+                        code: close_event.code(),
+                        reason: close_event.reason(),
+                        was_clean: close_event.was_clean(),
+                    }));
+                    shared.reconnect_later();
+                }) as Box<dyn FnMut(web_sys::CloseEvent)>);
+            ws.set_onclose(Some(onclose_callback.as_ref().unchecked_ref()));
+            onclose_callback
+        };
+
+        *self.callbacks.borrow_mut() = Some(Callbacks {
+            ws: ws.clone(),
+            _onmessage: onmessage,
+            _onerror: onerror,
+            _onopen: onopen,
+            _onclose: onclose,
+        });
+        *self.ws.borrow_mut() = Some(ws);
+        self.schedule_open_timeout();
+        Ok(())
     }
+}
+
+pub(crate) fn ws_receive(url: String, options: Options, on_event: EventHandler) -> Result<()> {
+    ws_connect(url, options, on_event).map(|sender| sender.forget())
+}
+
+/// The browser `WebSocket` API gives us very little control over the connection,
+/// so the following [`Options`] are not supported on web:
+/// * [`Options::max_frame_size`], [`Options::write_buffer_size`] and [`Options::max_write_buffer_size`]
+/// * [`Options::additional_headers`]
+/// * [`Options::tls`] (the browser handles TLS)
+/// * [`Options::connect_timeout`], [`Options::tls_handshake_timeout`] and [`Options::upgrade_timeout`]
+///   (use [`Options::open_timeout`] instead)
+///
+/// [`Options::max_message_size`] is supported, but only checked once a whole message has arrived.
+fn check_options(options: &Options) -> Result<()> {
+    let Options {
+        max_message_size: _, // supported
+        max_frame_size,
+        write_buffer_size,
+        max_write_buffer_size,
+        additional_headers,
+        subprotocols: _, // supported
+        tls,
+        reconnect: _, // supported
+        outbox: _,    // supported
+        heartbeat,
+        connect_timeout,
+        tls_handshake_timeout,
+        upgrade_timeout,
+        open_timeout: _, // supported
+        idle_timeout: _, // supported
+    } = options;
+    if let Some(heartbeat) = heartbeat {
+        if !matches!(
+            heartbeat.web_ping,
+            WsMessage::Text(_) | WsMessage::Binary(_)
+        ) {
+            return Err(Error::Options(
+                "`HeartbeatOptions::web_ping` must be a text or binary message".to_owned(),
+            ));
+        }
+    }
+    check_unsupported(&[
+        ("max_frame_size", max_frame_size.is_some()),
+        ("write_buffer_size", write_buffer_size.is_some()),
+        ("max_write_buffer_size", max_write_buffer_size.is_some()),
+        ("additional_headers", !additional_headers.is_empty()),
+        ("tls", *tls != TlsOptions::default()),
+        ("connect_timeout", connect_timeout.is_some()),
+        ("tls_handshake_timeout", tls_handshake_timeout.is_some()),
+        ("upgrade_timeout", upgrade_timeout.is_some()),
+    ])
+}
+
+/// Returns an error naming the first option that is set but not supported on web.
+///
+/// `unsupported` lists the names of the fields that the browser cannot honour,
+/// together with whether or not they have been set to a non-default value.
+fn check_unsupported(unsupported: &[(&str, bool)]) -> Result<()> {
+    for (name, is_set) in unsupported {
+        if *is_set {
+            return Err(Error::Options(format!(
+                "The web backend does not support `Options::{name}`"
+            )));
+        }
+    }
+    Ok(())
+}
+
+pub(crate) fn ws_connect(
+    url: String,
+    options: Options,
+    on_event: EventHandler,
+) -> Result<WsSender> {
+    check_options(&options)?;
+
+    let shared = Rc::new(Shared {
+        heartbeat_options: options.heartbeat.clone(),
+        heartbeat: RefCell::new(None),
+        heartbeat_timer: Cell::new(None),
+        opened_at: Cell::new(0.0),
+        url,
+        subprotocols: options.subprotocols.clone(),
+        on_event,
+        max_message_size: options.max_message_size,
+        ws: RefCell::new(None),
+        callbacks: RefCell::new(None),
+        backoff: RefCell::new(Backoff::new(&options)),
+        outbox: RefCell::new(Outbox::new(&options)),
+        timer: Cell::new(None),
+        open_timeout: options.open_timeout,
+        open_timer: Cell::new(None),
+        idle_timeout: options.idle_timeout,
+        idle_timer: Cell::new(None),
+        stopped: Cell::new(false),
+    });
+    shared.connect()?;
 
-    Ok(WsSender { ws: Some(ws) })
+    Ok(WsSender {
+        inner: Rc::new(Inner {
+            shared: RefCell::new(Some(shared)),
+        }),
+    })
 }
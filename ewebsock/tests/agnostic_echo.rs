@@ -0,0 +1,104 @@
+//! The [`ewebsock::agnostic`] backend, driven by a plain `block_on`, against a local echo server.
+
+#![cfg(all(feature = "agnostic", not(target_arch = "wasm32")))]
+
+mod common;
+
+use std::thread::JoinHandle;
+
+use ewebsock::{WsEvent, WsMessage};
+
+use common::TIMEOUT;
+
+/// Accept a single client, and echo its messages back until it closes the connection.
+fn echo_one() -> (String, JoinHandle<()>) {
+    common::serve_one(|mut socket| loop {
+        match socket.read() {
+            Ok(message @ (tungstenite::Message::Text(_) | tungstenite::Message::Binary(_))) => {
+                socket.send(message).unwrap();
+            }
+            Ok(_) => {}
+            Err(tungstenite::Error::ConnectionClosed) => return,
+            Err(err) => panic!("Echo server failed: {err}"),
+        }
+    })
+}
+
+fn recv(receiver: &ewebsock::WsReceiver) -> WsEvent {
+    receiver
+        .recv_timeout(TIMEOUT)
+        .expect("Timed out waiting for an event")
+}
+
+#[test]
+fn messages_are_echoed_in_order() {
+    let (url, server) = echo_one();
+
+    let (sender, receiver, connection) =
+        ewebsock::agnostic::connect(url, ewebsock::Options::default());
+    let client = std::thread::spawn(move || futures_lite::future::block_on(connection));
+
+    let messages: Vec<WsMessage> = (0..10)
+        .map(|i| {
+            if i % 2 == 0 {
+                WsMessage::Text(format!("message {i}"))
+            } else {
+                WsMessage::Binary(vec![i; i as usize])
+            }
+        })
+        .collect();
+
+    // Sent before the connection opens, so these go through the outbox:
+    for message in &messages[..5] {
+        sender.try_send(message.clone()).unwrap();
+    }
+    assert!(matches!(recv(&receiver), WsEvent::Opened(_)));
+    for message in &messages[5..] {
+        sender.try_send(message.clone()).unwrap();
+    }
+
+    for message in &messages {
+        match recv(&receiver) {
+            WsEvent::Message(echo) => assert_eq!(&echo, message),
+            event => panic!("Expected the echo of {message:?}, got: {event:?}"),
+        }
+    }
+
+    sender.close_with(1000, "done").unwrap();
+    match recv(&receiver) {
+        WsEvent::Closed(info) => assert_eq!(info.code, 1000),
+        event => panic!("Expected the connection to close, got: {event:?}"),
+    }
+
+    client.join().unwrap();
+    server.join().unwrap();
+    assert!(matches!(
+        sender.try_send(WsMessage::Text("too late".into())),
+        Err(ewebsock::Error::Closed)
+    ));
+}
+
+#[test]
+fn recv_async_works_on_any_executor() {
+    let (url, server) = echo_one();
+
+    let (sender, receiver, connection) =
+        ewebsock::agnostic::connect(url, ewebsock::Options::default());
+    let client = std::thread::spawn(move || futures_lite::future::block_on(connection));
+
+    futures_lite::future::block_on(async {
+        assert!(matches!(
+            receiver.recv_async().await,
+            Some(WsEvent::Opened(_))
+        ));
+        sender.send(WsMessage::Text("hello".into()));
+        match receiver.recv_async().await {
+            Some(WsEvent::Message(echo)) => assert_eq!(echo, WsMessage::Text("hello".into())),
+            event => panic!("Expected the echo, got: {event:?}"),
+        }
+    });
+
+    drop(sender);
+    client.join().unwrap();
+    server.join().unwrap();
+}
@@ -0,0 +1,250 @@
+//! Returning [`ControlFlow::Break`] from the event handler (which is what happens when the
+//! [`ewebsock::WsReceiver`] is dropped) must close the connection with a close frame,
+//! and end the thread or task that handles it.
+//!
+//! That thread or task owns the TCP connection, so it has ended once the client hangs up.
+//!
+//! Closing with [`ewebsock::WsSender::close_with`] must send its code and reason.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use std::{
+    any::Any,
+    io::Read as _,
+    ops::ControlFlow,
+    sync::mpsc,
+    thread::JoinHandle,
+    time::{Duration, Instant},
+};
+
+use ewebsock::{Options, WsEvent, WsReceiver};
+
+use common::TIMEOUT;
+
+/// How the client ended the connection, as seen by the server.
+#[derive(Debug, PartialEq, Eq)]
+struct Ending {
+    /// The code in the close frame sent by the client.
+    close_code: Option<u16>,
+
+    /// Did the client then hang up?
+    hung_up: bool,
+}
+
+/// Accept a single client, send it a text message once `go` fires, and report how it ended.
+///
+/// Unless `acknowledge`, the close frame of the client is never acknowledged.
+fn serve_one(go: mpsc::Receiver<()>, acknowledge: bool) -> (String, JoinHandle<Ending>) {
+    common::serve_one(move |mut socket| {
+        go.recv().unwrap();
+        socket.send(tungstenite::Message::text("hello")).unwrap();
+
+        let close_code = loop {
+            match socket.read() {
+                Ok(tungstenite::Message::Close(frame)) => {
+                    break frame.map(|frame| u16::from(frame.code));
+                }
+                Ok(_) => {}
+                Err(err) => panic!("Expected a close frame, got: {err}"),
+            }
+        };
+
+        // Acknowledge the close, and wait for the client to hang up:
+        if acknowledge {
+            socket.flush().ok();
+        }
+        let hung_up = matches!(socket.get_mut().read(&mut [0; 1]), Ok(0));
+
+        Ending {
+            close_code,
+            hung_up,
+        }
+    })
+}
+
+fn wait_for_opened(receiver: &WsReceiver) {
+    let deadline = Instant::now() + TIMEOUT;
+    while Instant::now() < deadline {
+        match receiver.try_recv() {
+            Some(WsEvent::Opened(_)) => return,
+            Some(event) => panic!("Unexpected event: {event:?}"),
+            None => std::thread::sleep(Duration::from_millis(10)),
+        }
+    }
+    panic!("Timed out waiting for the connection to open");
+}
+
+const CLEAN_ENDING: Ending = Ending {
+    close_code: Some(1000),
+    hung_up: true,
+};
+
+type EventHandler = Box<dyn Send + Fn(WsEvent) -> ControlFlow<()>>;
+
+/// The `ws_connect` of a backend. Returns what keeps the connection open.
+type WsConnect = fn(String, EventHandler) -> Box<dyn Any>;
+
+/// The `ws_receive` of a backend.
+type WsReceive = fn(String, EventHandler);
+
+fn dropping_the_receiver_closes_the_connection(ws_connect: WsConnect) {
+    let (go, go_rx) = mpsc::channel();
+    let (url, server) = serve_one(go_rx, true);
+
+    let (receiver, on_event) = WsReceiver::new();
+    let sender = ws_connect(url, on_event);
+    wait_for_opened(&receiver);
+    drop(receiver);
+    go.send(()).unwrap();
+
+    assert_eq!(server.join().unwrap(), CLEAN_ENDING);
+    drop(sender);
+}
+
+fn break_on_opened_closes_the_connection(ws_connect: WsConnect) {
+    let (go, go_rx) = mpsc::channel();
+    let (url, server) = serve_one(go_rx, true);
+    go.send(()).unwrap();
+
+    let sender = ws_connect(
+        url,
+        Box::new(|event| match event {
+            WsEvent::Opened(_) => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        }),
+    );
+
+    assert_eq!(server.join().unwrap(), CLEAN_ENDING);
+    drop(sender);
+}
+
+fn break_in_ws_receive_closes_the_connection(ws_receive: WsReceive) {
+    let (go, go_rx) = mpsc::channel();
+    let (url, server) = serve_one(go_rx, true);
+    go.send(()).unwrap();
+
+    ws_receive(
+        url,
+        Box::new(|event| match event {
+            WsEvent::Message(_) => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        }),
+    );
+
+    assert_eq!(server.join().unwrap(), CLEAN_ENDING);
+}
+
+fn break_in_ws_receive_gives_up_on_a_silent_server(ws_receive: WsReceive) {
+    let (go, go_rx) = mpsc::channel();
+    let (url, server) = serve_one(go_rx, false);
+    go.send(()).unwrap();
+
+    ws_receive(
+        url,
+        Box::new(|event| match event {
+            WsEvent::Message(_) => ControlFlow::Break(()),
+            _ => ControlFlow::Continue(()),
+        }),
+    );
+
+    // The client hangs up once it is tired of waiting for the acknowledgement:
+    assert_eq!(server.join().unwrap(), CLEAN_ENDING);
+}
+
+#[test]
+fn close_with_sends_the_code_and_reason() {
+    let (url, server) = common::serve_one(|mut socket| loop {
+        match socket.read() {
+            Ok(tungstenite::Message::Close(frame)) => {
+                socket.flush().ok(); // Acknowledge the close
+                break frame.map(|frame| (u16::from(frame.code), frame.reason.into_owned()));
+            }
+            Ok(_) => {}
+            Err(err) => panic!("Expected a close frame, got: {err}"),
+        }
+    });
+
+    let (sender, receiver) = ewebsock::connect(url).unwrap();
+    wait_for_opened(&receiver);
+    sender.close_with(4000, "going away").unwrap();
+
+    assert_eq!(
+        server.join().unwrap(),
+        Some((4000, "going away".to_owned()))
+    );
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(WsEvent::Closed(info)) => assert_eq!(info.code, 4000),
+        event => panic!("Expected the connection to close, got: {event:?}"),
+    }
+}
+
+mod thread {
+    use super::*;
+
+    fn ws_connect(url: String, on_event: EventHandler) -> Box<dyn Any> {
+        Box::new(ewebsock::thread::ws_connect(url, Options::default(), on_event).unwrap())
+    }
+
+    fn ws_receive(url: String, on_event: EventHandler) {
+        ewebsock::thread::ws_receive(url, Options::default(), on_event).unwrap();
+    }
+
+    #[test]
+    fn dropping_the_receiver_closes_the_connection() {
+        super::dropping_the_receiver_closes_the_connection(ws_connect);
+    }
+
+    #[test]
+    fn break_on_opened_closes_the_connection() {
+        super::break_on_opened_closes_the_connection(ws_connect);
+    }
+
+    #[test]
+    fn break_in_ws_receive_closes_the_connection() {
+        super::break_in_ws_receive_closes_the_connection(ws_receive);
+    }
+
+    #[test]
+    fn break_in_ws_receive_gives_up_on_a_silent_server() {
+        super::break_in_ws_receive_gives_up_on_a_silent_server(ws_receive);
+    }
+}
+
+#[cfg(feature = "tokio")]
+mod tokio {
+    use super::*;
+
+    use ewebsock::tokio::TokioRuntime;
+
+    fn ws_connect(url: String, on_event: EventHandler) -> Box<dyn Any> {
+        let options = Options::default();
+        Box::new(ewebsock::tokio::ws_connect(url, options, TokioRuntime::Shared, on_event).unwrap())
+    }
+
+    fn ws_receive(url: String, on_event: EventHandler) {
+        ewebsock::tokio::ws_receive(url, Options::default(), TokioRuntime::Shared, on_event)
+            .unwrap();
+    }
+
+    #[test]
+    fn dropping_the_receiver_closes_the_connection() {
+        super::dropping_the_receiver_closes_the_connection(ws_connect);
+    }
+
+    #[test]
+    fn break_on_opened_closes_the_connection() {
+        super::break_on_opened_closes_the_connection(ws_connect);
+    }
+
+    #[test]
+    fn break_in_ws_receive_closes_the_connection() {
+        super::break_in_ws_receive_closes_the_connection(ws_receive);
+    }
+
+    #[test]
+    fn break_in_ws_receive_gives_up_on_a_silent_server() {
+        super::break_in_ws_receive_gives_up_on_a_silent_server(ws_receive);
+    }
+}
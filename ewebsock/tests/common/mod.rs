@@ -0,0 +1,58 @@
+//! Local servers for the integration tests to connect to.
+
+// Each test file uses only some of this:
+#![allow(dead_code)]
+
+use std::{
+    net::{TcpListener, TcpStream},
+    thread::JoinHandle,
+    time::Duration,
+};
+
+use tungstenite::handshake::server::{Callback, NoCallback};
+
+/// How long to wait for anything that should happen.
+pub const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The server side of a WebSocket connection.
+pub type Socket = tungstenite::WebSocket<TcpStream>;
+
+/// Listen on a free local port, and return the `ws://` URL of it.
+pub fn listen() -> (TcpListener, String) {
+    let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+    let url = format!("ws://{}", listener.local_addr().unwrap());
+    (listener, url)
+}
+
+/// Accept the next client, with reads that time out after [`TIMEOUT`].
+pub fn accept_tcp(listener: &TcpListener) -> TcpStream {
+    let (stream, _) = listener.accept().unwrap();
+    stream.set_read_timeout(Some(TIMEOUT)).unwrap();
+    stream
+}
+
+/// Accept the next client, and do the WebSocket handshake with it.
+pub fn accept(listener: &TcpListener) -> Socket {
+    tungstenite::accept(accept_tcp(listener)).unwrap()
+}
+
+/// Accept a single client on another thread, and pass it to `serve`.
+pub fn serve_one<T: Send + 'static>(
+    serve: impl FnOnce(Socket) -> T + Send + 'static,
+) -> (String, JoinHandle<T>) {
+    serve_one_hdr(NoCallback, serve)
+}
+
+/// Like [`serve_one`], but with a `callback` that sees the handshake request,
+/// and can change the response (see [`tungstenite::accept_hdr`]).
+pub fn serve_one_hdr<T: Send + 'static>(
+    callback: impl Callback + Send + 'static,
+    serve: impl FnOnce(Socket) -> T + Send + 'static,
+) -> (String, JoinHandle<T>) {
+    let (listener, url) = listen();
+    let server = std::thread::spawn(move || {
+        let socket = tungstenite::accept_hdr(accept_tcp(&listener), callback).unwrap();
+        serve(socket)
+    });
+    (url, server)
+}
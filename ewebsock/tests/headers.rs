@@ -0,0 +1,45 @@
+//! [`ewebsock::Options::additional_headers`] are sent along with the handshake request.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use std::sync::mpsc;
+
+use ewebsock::{Options, WsEvent};
+use tungstenite::handshake::server::{ErrorResponse, Request, Response};
+
+use common::TIMEOUT;
+
+#[test]
+fn additional_headers_reach_the_server() {
+    let (header_tx, header_rx) = mpsc::channel();
+    let (url, server) = common::serve_one_hdr(
+        move |request: &Request, response: Response| -> Result<Response, ErrorResponse> {
+            let header = request
+                .headers()
+                .get("x-api-key")
+                .map(|value| value.to_str().unwrap().to_owned());
+            header_tx.send(header).unwrap();
+            Ok(response)
+        },
+        |mut socket| while socket.read().is_ok() {},
+    );
+
+    let options = Options {
+        additional_headers: vec![("X-Api-Key".to_owned(), "secret".to_owned())],
+        ..Default::default()
+    };
+    let (sender, receiver) = ewebsock::connect_with_options(url, options).unwrap();
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Opened(_))
+    ));
+    assert_eq!(
+        header_rx.recv_timeout(TIMEOUT).unwrap().as_deref(),
+        Some("secret")
+    );
+
+    drop(sender);
+    server.join().unwrap();
+}
@@ -0,0 +1,78 @@
+//! With [`ewebsock::Options::heartbeat`], the server is pinged,
+//! and a server that stops answering fails the connection.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use std::time::Duration;
+
+use ewebsock::{Error, HeartbeatOptions, Options, TimeoutKind, WsEvent};
+
+use common::TIMEOUT;
+
+#[test]
+fn round_trip_time() {
+    let (url, server) = common::serve_one(|mut socket| {
+        // Reading answers the pings:
+        while socket.read().is_ok() {}
+    });
+
+    let options = Options {
+        heartbeat: Some(HeartbeatOptions {
+            interval: Duration::from_millis(50),
+            timeout: TIMEOUT,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (sender, receiver) = ewebsock::connect_with_options(url, options).unwrap();
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Opened(_))
+    ));
+    loop {
+        match receiver.recv_timeout(TIMEOUT) {
+            Ok(WsEvent::RoundTripTime(rtt)) => {
+                assert!(rtt < TIMEOUT, "{rtt:?}");
+                break;
+            }
+            Ok(WsEvent::Message(_)) => {}
+            event => panic!("Expected a round-trip time, got: {event:?}"),
+        }
+    }
+
+    drop(sender);
+    server.join().unwrap();
+}
+
+#[test]
+fn heartbeat_timeout() {
+    let (url, server) = common::serve_one(|mut socket| {
+        // Swallow the pings without answering them, until the client hangs up:
+        std::io::copy(socket.get_mut(), &mut std::io::sink()).ok();
+    });
+
+    let options = Options {
+        heartbeat: Some(HeartbeatOptions {
+            interval: Duration::from_millis(50),
+            timeout: Duration::from_millis(200),
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (_sender, receiver) = ewebsock::connect_with_options(url, options).unwrap();
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Opened(_))
+    ));
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(WsEvent::Error(Error::Timeout(TimeoutKind::Heartbeat))) => {}
+        event => panic!("Expected a heartbeat timeout, got: {event:?}"),
+    }
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Closed(_))
+    ));
+    server.join().unwrap();
+}
@@ -0,0 +1,49 @@
+//! Messages larger than [`ewebsock::Options::max_message_size`] must fail the connection
+//! with [`ewebsock::Error::MessageTooLarge`].
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use ewebsock::{Error, Options, WsEvent};
+
+use common::TIMEOUT;
+
+#[test]
+fn max_message_size() {
+    let (url, server) = common::serve_one(|mut socket| {
+        socket
+            .send(tungstenite::Message::binary(vec![0; 100]))
+            .unwrap();
+        socket
+            .send(tungstenite::Message::binary(vec![0; 101]))
+            .unwrap();
+        while socket.read().is_ok() {}
+    });
+
+    let options = Options {
+        max_message_size: Some(100),
+        ..Default::default()
+    };
+    let (_sender, receiver) = ewebsock::connect_with_options(url, options).unwrap();
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Opened(_))
+    ));
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Message(_))
+    ));
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(WsEvent::Error(Error::MessageTooLarge {
+            size: 101,
+            max_size: 100,
+        })) => {}
+        event => panic!("Expected the message to be too large, got: {event:?}"),
+    }
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Closed(_))
+    ));
+    server.join().unwrap();
+}
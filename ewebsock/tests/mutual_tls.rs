@@ -0,0 +1,163 @@
+//! [`ewebsock::TlsOptions`] against a local server that only accepts clients
+//! with a certificate signed by its own, freshly generated, certificate authority.
+
+#![cfg(all(feature = "tls", not(target_arch = "wasm32")))]
+
+mod common;
+
+use std::{sync::Arc, thread::JoinHandle};
+
+use ewebsock::{Certificate, ClientIdentity, Options, PrivateKey, TlsOptions, WsEvent, WsMessage};
+
+use common::TIMEOUT;
+
+/// The certificates of a test, all signed by the same certificate authority.
+struct Pki {
+    ca: rcgen::Certificate,
+    server: rcgen::Certificate,
+    client: rcgen::Certificate,
+}
+
+/// A certificate with the given common name and subject alternative names.
+fn certificate(name: &str, alt_names: &[&str], is_ca: bool) -> rcgen::Certificate {
+    let mut params = rcgen::CertificateParams::new(
+        alt_names
+            .iter()
+            .map(|&name| name.to_owned())
+            .collect::<Vec<_>>(),
+    );
+    params.distinguished_name = rcgen::DistinguishedName::new();
+    params
+        .distinguished_name
+        .push(rcgen::DnType::CommonName, name);
+    if is_ca {
+        params.is_ca = rcgen::IsCa::Ca(rcgen::BasicConstraints::Unconstrained);
+    }
+    rcgen::Certificate::from_params(params).unwrap()
+}
+
+impl Pki {
+    fn generate() -> Self {
+        Self {
+            ca: certificate("ewebsock test CA", &[], true),
+            server: certificate("ewebsock test server", &["localhost"], false),
+            client: certificate("ewebsock test client", &[], false),
+        }
+    }
+
+    /// What the client should be configured with.
+    fn client_options(&self, with_identity: bool) -> Options {
+        Options {
+            tls: TlsOptions {
+                root_certificates: vec![Certificate::Pem(
+                    self.ca.serialize_pem().unwrap().into_bytes(),
+                )],
+                client_identity: with_identity.then(|| ClientIdentity {
+                    certificates: vec![Certificate::Pem(
+                        self.client
+                            .serialize_pem_with_signer(&self.ca)
+                            .unwrap()
+                            .into_bytes(),
+                    )],
+                    private_key: PrivateKey::Pem(
+                        self.client.serialize_private_key_pem().into_bytes(),
+                    ),
+                }),
+                ..Default::default()
+            },
+            ..Default::default()
+        }
+    }
+
+    /// Accept a single client, greet it, and wait for it to hang up.
+    fn serve_one(&self) -> (String, JoinHandle<()>) {
+        let mut roots = rustls::RootCertStore::empty();
+        roots
+            .add(&rustls::Certificate(self.ca.serialize_der().unwrap()))
+            .unwrap();
+        let config = rustls::ServerConfig::builder()
+            .with_safe_defaults()
+            .with_client_cert_verifier(
+                rustls::server::AllowAnyAuthenticatedClient::new(roots).boxed(),
+            )
+            .with_single_cert(
+                vec![rustls::Certificate(
+                    self.server.serialize_der_with_signer(&self.ca).unwrap(),
+                )],
+                rustls::PrivateKey(self.server.serialize_private_key_der()),
+            )
+            .unwrap();
+
+        let (listener, _) = common::listen();
+        // The server certificate is for `localhost`:
+        let url = format!("wss://localhost:{}", listener.local_addr().unwrap().port());
+        let server = std::thread::spawn(move || {
+            let stream = common::accept_tcp(&listener);
+            let connection = rustls::ServerConnection::new(Arc::new(config)).unwrap();
+            let stream = rustls::StreamOwned::new(connection, stream);
+            // Fails for clients without a valid certificate:
+            if let Ok(mut socket) = tungstenite::accept(stream) {
+                socket.send(tungstenite::Message::text("hello")).unwrap();
+                while socket.read().is_ok() {}
+            }
+        });
+
+        (url, server)
+    }
+}
+
+fn expect_greeting(receiver: &ewebsock::WsReceiver) {
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Opened(_))
+    ));
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(WsEvent::Message(message)) => assert_eq!(message, WsMessage::Text("hello".into())),
+        event => panic!("Expected the greeting, got: {event:?}"),
+    }
+}
+
+#[test]
+fn client_certificate() {
+    let pki = Pki::generate();
+    let (url, server) = pki.serve_one();
+
+    let (sender, receiver) = ewebsock::connect_with_options(url, pki.client_options(true)).unwrap();
+    expect_greeting(&receiver);
+
+    drop(sender);
+    server.join().unwrap();
+}
+
+#[test]
+fn missing_client_certificate() {
+    let pki = Pki::generate();
+    let (url, server) = pki.serve_one();
+
+    let (_sender, receiver) =
+        ewebsock::connect_with_options(url, pki.client_options(false)).unwrap();
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(WsEvent::Error(_)) => {}
+        event => panic!("Expected the server to reject the client, got: {event:?}"),
+    }
+
+    server.join().unwrap();
+}
+
+#[cfg(feature = "tokio")]
+#[test]
+fn client_certificate_with_tokio() {
+    let pki = Pki::generate();
+    let (url, server) = pki.serve_one();
+
+    let (sender, receiver) = ewebsock::tokio::connect(
+        url,
+        pki.client_options(true),
+        ewebsock::tokio::TokioRuntime::Shared,
+    )
+    .unwrap();
+    expect_greeting(&receiver);
+
+    drop(sender);
+    server.join().unwrap();
+}
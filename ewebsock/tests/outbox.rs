@@ -0,0 +1,102 @@
+//! Messages sent before the connection opens are buffered in the outbox,
+//! and a full outbox follows its [`ewebsock::OverflowPolicy`].
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use ewebsock::{Error, Options, OutboxOptions, OverflowPolicy, Result, WsEvent, WsMessage};
+
+use common::TIMEOUT;
+
+/// What happened to three messages sent before the connection opened,
+/// with room for only two of them.
+struct Outcome {
+    /// What `try_send` returned for each message.
+    sent: Vec<Result<()>>,
+
+    /// The messages the server received.
+    received: Vec<String>,
+
+    /// The events after [`WsEvent::Opened`].
+    events: Vec<WsEvent>,
+}
+
+fn send_three_before_open(overflow: OverflowPolicy) -> Outcome {
+    let (listener, url) = common::listen();
+    let options = Options {
+        outbox: OutboxOptions {
+            capacity: 2,
+            overflow,
+        },
+        ..Default::default()
+    };
+    let (sender, receiver) = ewebsock::connect_with_options(url, options).unwrap();
+
+    // The server has not accepted the connection yet:
+    let sent = ["a", "b", "c"]
+        .into_iter()
+        .map(|text| sender.try_send(WsMessage::Text(text.to_owned())))
+        .collect();
+
+    let server = std::thread::spawn(move || {
+        let mut socket = common::accept(&listener);
+        let mut received = Vec::new();
+        loop {
+            match socket.read() {
+                Ok(tungstenite::Message::Text(text)) => received.push(text),
+                Ok(tungstenite::Message::Close(_)) => {
+                    socket.flush().ok(); // Acknowledge the close
+                    return received;
+                }
+                Ok(_) => {}
+                Err(err) => panic!("Expected the client to close, got: {err}"),
+            }
+        }
+    });
+
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Opened(_))
+    ));
+    sender.close().unwrap();
+    let events = receiver.iter().collect();
+
+    Outcome {
+        sent,
+        received: server.join().unwrap(),
+        events,
+    }
+}
+
+fn dropped(events: &[WsEvent]) -> Option<usize> {
+    events.iter().find_map(|event| match event {
+        WsEvent::OutgoingDropped { count } => Some(*count),
+        _ => None,
+    })
+}
+
+#[test]
+fn drop_oldest() {
+    let outcome = send_three_before_open(OverflowPolicy::DropOldest);
+    assert!(outcome.sent.iter().all(Result::is_ok));
+    assert_eq!(outcome.received, vec!["b", "c"]);
+    assert_eq!(dropped(&outcome.events), Some(1));
+}
+
+#[test]
+fn drop_newest() {
+    let outcome = send_three_before_open(OverflowPolicy::DropNewest);
+    assert!(outcome.sent.iter().all(Result::is_ok));
+    assert_eq!(outcome.received, vec!["a", "b"]);
+    assert_eq!(dropped(&outcome.events), Some(1));
+}
+
+#[test]
+fn error_when_full() {
+    let outcome = send_three_before_open(OverflowPolicy::Error);
+    assert!(outcome.sent[..2].iter().all(Result::is_ok));
+    assert!(matches!(outcome.sent[2], Err(Error::OutboxFull)));
+    assert_eq!(outcome.received, vec!["a", "b"]);
+    assert_eq!(dropped(&outcome.events), None);
+}
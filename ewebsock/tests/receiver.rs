@@ -0,0 +1,58 @@
+//! The blocking ways to receive events from a [`ewebsock::WsReceiver`].
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use std::{sync::mpsc::RecvTimeoutError, time::Duration};
+
+use ewebsock::{WsEvent, WsMessage};
+
+use common::TIMEOUT;
+
+#[test]
+fn recv_timeout_without_events() {
+    let (_listener, url) = common::listen();
+    let (_sender, receiver) = ewebsock::connect(url).unwrap();
+
+    // The server never accepts the connection:
+    assert!(matches!(
+        receiver.recv_timeout(Duration::from_millis(100)),
+        Err(RecvTimeoutError::Timeout)
+    ));
+    assert!(receiver.try_iter().next().is_none());
+}
+
+#[test]
+fn recv_until_the_connection_ends() {
+    let (url, server) = common::serve_one(|mut socket| {
+        socket.send(tungstenite::Message::text("hello")).unwrap();
+        socket.send(tungstenite::Message::text("bye")).unwrap();
+        socket.close(None).unwrap();
+        while socket.read().is_ok() {} // Wait for the client to acknowledge the close
+    });
+
+    let (_sender, receiver) = ewebsock::connect(url).unwrap();
+    assert!(matches!(receiver.recv(), Some(WsEvent::Opened(_))));
+    match receiver.recv() {
+        Some(WsEvent::Message(WsMessage::Text(text))) => assert_eq!(text, "hello"),
+        event => panic!("Expected a greeting, got: {event:?}"),
+    }
+
+    // Ends once the connection has ended:
+    let rest: Vec<WsEvent> = receiver.iter().collect();
+    assert!(
+        matches!(
+            rest.as_slice(),
+            [WsEvent::Message(WsMessage::Text(_)), WsEvent::Closed(_)]
+        ),
+        "{rest:?}"
+    );
+
+    assert!(receiver.recv().is_none());
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Err(RecvTimeoutError::Disconnected)
+    ));
+    server.join().unwrap();
+}
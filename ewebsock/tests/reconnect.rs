@@ -0,0 +1,69 @@
+//! With [`ewebsock::Options::reconnect`], a connection closed by the server
+//! is re-established, and reported with [`ewebsock::WsEvent::Reconnected`].
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use std::time::Duration;
+
+use ewebsock::{Options, ReconnectOptions, WsEvent, WsMessage};
+
+use common::TIMEOUT;
+
+#[test]
+fn reconnect_after_the_server_closes() {
+    let (listener, url) = common::listen();
+    let server = std::thread::spawn(move || {
+        // Greet, and then close the first connection:
+        let mut socket = common::accept(&listener);
+        socket.send(tungstenite::Message::text("first")).unwrap();
+        socket.close(None).unwrap();
+        while socket.read().is_ok() {} // Wait for the client to acknowledge the close
+
+        // Greet the second connection, and keep it until the client hangs up:
+        let mut socket = common::accept(&listener);
+        socket.send(tungstenite::Message::text("second")).unwrap();
+        while socket.read().is_ok() {}
+    });
+
+    let options = Options {
+        reconnect: Some(ReconnectOptions {
+            initial_delay: Duration::from_millis(50),
+            jitter: 0.0,
+            ..Default::default()
+        }),
+        ..Default::default()
+    };
+    let (sender, receiver) = ewebsock::connect_with_options(url, options).unwrap();
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Opened(_))
+    ));
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(WsEvent::Message(WsMessage::Text(text))) => assert_eq!(text, "first"),
+        event => panic!("Expected the first greeting, got: {event:?}"),
+    }
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Closed(_))
+    ));
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(WsEvent::Reconnecting { attempt, delay }) => {
+            assert_eq!(attempt, 1);
+            assert_eq!(delay, Duration::from_millis(50));
+        }
+        event => panic!("Expected to reconnect, got: {event:?}"),
+    }
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Reconnected(_))
+    ));
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(WsEvent::Message(WsMessage::Text(text))) => assert_eq!(text, "second"),
+        event => panic!("Expected the second greeting, got: {event:?}"),
+    }
+
+    drop(sender);
+    server.join().unwrap();
+}
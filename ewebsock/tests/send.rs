@@ -0,0 +1,57 @@
+//! [`ewebsock::WsSender::try_send`] reports why a message cannot be sent,
+//! and [`ewebsock::WsSender::buffered_amount`] what has not been written yet.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use ewebsock::{Error, WsEvent, WsMessage};
+
+use common::TIMEOUT;
+
+#[test]
+fn try_send_after_close() {
+    let (_listener, url) = common::listen();
+    let (sender, _receiver) = ewebsock::connect(url).unwrap();
+
+    sender.close().unwrap();
+    assert!(matches!(
+        sender.try_send(WsMessage::Text("too late".into())),
+        Err(Error::Closed)
+    ));
+    assert_eq!(sender.buffered_amount(), 0);
+}
+
+#[test]
+fn buffered_until_written() {
+    let (listener, url) = common::listen();
+    let (sender, receiver) = ewebsock::connect(url).unwrap();
+
+    // The server has not accepted the connection yet:
+    sender.try_send(WsMessage::Text("hello".into())).unwrap();
+    sender.try_send(WsMessage::Binary(vec![1, 2, 3])).unwrap();
+    assert_eq!(sender.buffered_amount(), 8);
+
+    let server = std::thread::spawn(move || {
+        let mut socket = common::accept(&listener);
+        for _ in 0..2 {
+            socket.read().unwrap();
+        }
+        socket.send(tungstenite::Message::text("got them")).unwrap();
+        while socket.read().is_ok() {}
+    });
+
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Opened(_))
+    ));
+    // The reply can only arrive once both messages have been written:
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Message(_))
+    ));
+    assert_eq!(sender.buffered_amount(), 0);
+
+    drop(sender);
+    server.join().unwrap();
+}
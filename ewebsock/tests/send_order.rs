@@ -0,0 +1,52 @@
+//! The tokio backend writes messages in the order they were sent,
+//! even when they are sent faster than they can be written.
+
+#![cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+
+mod common;
+
+use std::time::Duration;
+
+use ewebsock::{tokio::TokioRuntime, Error, Options, WsEvent, WsMessage};
+
+use common::TIMEOUT;
+
+#[test]
+fn tokio_sends_in_order() {
+    let (url, server) = common::serve_one(|mut socket| {
+        let mut received = Vec::new();
+        loop {
+            match socket.read() {
+                Ok(tungstenite::Message::Text(text)) => received.push(text),
+                Ok(tungstenite::Message::Close(_)) => {
+                    socket.flush().ok(); // Acknowledge the close
+                    return received;
+                }
+                Ok(_) => {}
+                Err(err) => panic!("Expected the client to close, got: {err}"),
+            }
+        }
+    });
+
+    let (sender, receiver) =
+        ewebsock::tokio::connect(url, Options::default(), TokioRuntime::Shared).unwrap();
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Opened(_))
+    ));
+
+    let messages: Vec<String> = (0..1000).map(|i| format!("message {i}")).collect();
+    for message in &messages {
+        // Wait for the connection to catch up whenever the send queue is full:
+        loop {
+            match sender.try_send(WsMessage::Text(message.clone())) {
+                Ok(()) => break,
+                Err(Error::SendQueueFull) => std::thread::sleep(Duration::from_millis(1)),
+                Err(err) => panic!("Failed to send: {err}"),
+            }
+        }
+    }
+    sender.close().unwrap();
+
+    assert_eq!(server.join().unwrap(), messages);
+}
@@ -0,0 +1,72 @@
+//! [`ewebsock::WsSender`] as a [`futures_sink::Sink`] waits for the connection to open,
+//! and flushes the outbox too.
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use std::pin::Pin;
+
+use ewebsock::{Error, Result, WsEvent, WsMessage, WsSender};
+use futures_lite::future;
+use futures_sink::Sink;
+
+use common::TIMEOUT;
+
+fn poll_ready_once(sender: &mut WsSender) -> Option<Result<()>> {
+    future::block_on(future::poll_once(future::poll_fn(|cx| {
+        Pin::new(&mut *sender).poll_ready(cx)
+    })))
+}
+
+fn poll_flush_once(sender: &mut WsSender) -> Option<Result<()>> {
+    future::block_on(future::poll_once(future::poll_fn(|cx| {
+        Pin::new(&mut *sender).poll_flush(cx)
+    })))
+}
+
+#[test]
+fn ready_once_open() {
+    let (listener, url) = common::listen();
+    let (mut sender, receiver) = ewebsock::connect(url).unwrap();
+
+    // The server has not accepted the connection yet:
+    assert!(poll_ready_once(&mut sender).is_none());
+    sender.try_send(WsMessage::Text("buffered".into())).unwrap();
+    assert!(poll_flush_once(&mut sender).is_none());
+
+    let server = std::thread::spawn(move || {
+        let mut socket = common::accept(&listener);
+        let mut received = Vec::new();
+        loop {
+            match socket.read() {
+                Ok(tungstenite::Message::Text(text)) => received.push(text),
+                Ok(tungstenite::Message::Close(_)) => {
+                    socket.flush().ok(); // Acknowledge the close
+                    return received;
+                }
+                Ok(_) => {}
+                Err(err) => panic!("Expected the client to close, got: {err}"),
+            }
+        }
+    });
+
+    // Waits for the connection to open, and the outbox to be written:
+    future::block_on(future::poll_fn(|cx| Pin::new(&mut sender).poll_flush(cx))).unwrap();
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Opened(_))
+    ));
+
+    future::block_on(future::poll_fn(|cx| Pin::new(&mut sender).poll_ready(cx))).unwrap();
+    Pin::new(&mut sender)
+        .start_send(WsMessage::Text("sent".into()))
+        .unwrap();
+    future::block_on(future::poll_fn(|cx| Pin::new(&mut sender).poll_close(cx))).unwrap();
+
+    assert_eq!(server.join().unwrap(), vec!["buffered", "sent"]);
+    assert!(matches!(
+        poll_ready_once(&mut sender),
+        Some(Err(Error::Closed))
+    ));
+}
@@ -0,0 +1,81 @@
+//! The subprotocol picked by the server must be one of [`ewebsock::Options::subprotocols`],
+//! and is reported in [`ewebsock::WsEvent::Opened`].
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use std::{sync::mpsc, thread::JoinHandle};
+
+use ewebsock::{Error, Options, WsEvent};
+use tungstenite::{
+    handshake::server::{ErrorResponse, Request, Response},
+    http::HeaderValue,
+};
+
+use common::TIMEOUT;
+
+/// Accept a single client, and pick the given subprotocol,
+/// whatever the client offered.
+///
+/// The offer of the client is sent to `offered`.
+fn serve_one_picking(
+    protocol: &'static str,
+    offered: mpsc::Sender<Option<String>>,
+) -> (String, JoinHandle<()>) {
+    common::serve_one_hdr(
+        move |request: &Request, mut response: Response| -> Result<Response, ErrorResponse> {
+            let offer = request
+                .headers()
+                .get("Sec-WebSocket-Protocol")
+                .map(|value| value.to_str().unwrap().to_owned());
+            offered.send(offer).unwrap();
+            response
+                .headers_mut()
+                .insert("Sec-WebSocket-Protocol", HeaderValue::from_static(protocol));
+            Ok(response)
+        },
+        |mut socket| while socket.read().is_ok() {},
+    )
+}
+
+fn options() -> Options {
+    Options {
+        subprotocols: vec!["chat.v2".to_owned(), "chat.v1".to_owned()],
+        ..Default::default()
+    }
+}
+
+#[test]
+fn negotiated_protocol_is_reported() {
+    let (offered_tx, offered) = mpsc::channel();
+    let (url, server) = serve_one_picking("chat.v1", offered_tx);
+
+    let (sender, receiver) = ewebsock::connect_with_options(url, options()).unwrap();
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(WsEvent::Opened(info)) => assert_eq!(info.protocol.as_deref(), Some("chat.v1")),
+        event => panic!("Expected the connection to open, got: {event:?}"),
+    }
+    assert_eq!(
+        offered.recv_timeout(TIMEOUT).unwrap().as_deref(),
+        Some("chat.v2, chat.v1")
+    );
+
+    drop(sender);
+    server.join().unwrap();
+}
+
+#[test]
+fn protocol_that_was_not_offered_fails() {
+    let (offered_tx, _offered) = mpsc::channel();
+    let (url, server) = serve_one_picking("mqtt", offered_tx);
+
+    let (_sender, receiver) = ewebsock::connect_with_options(url, options()).unwrap();
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(WsEvent::Error(Error::Protocol(_))) => {}
+        event => panic!("Expected the connection to fail, got: {event:?}"),
+    }
+
+    // The client hangs up:
+    server.join().unwrap();
+}
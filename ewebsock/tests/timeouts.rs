@@ -0,0 +1,85 @@
+//! A server that stops responding must fail the connection
+//! with the matching [`ewebsock::TimeoutKind`].
+
+#![cfg(not(target_arch = "wasm32"))]
+
+mod common;
+
+use std::time::Duration;
+
+use ewebsock::{Error, Options, TimeoutKind, WsEvent};
+
+use common::TIMEOUT;
+
+/// Connect to a server that never answers the upgrade request,
+/// and return the error the connection fails with.
+fn connect_to_silent_server(options: Options) -> Error {
+    let (listener, url) = common::listen();
+
+    let (_sender, receiver) = ewebsock::connect_with_options(url, options).unwrap();
+    let _stream = common::accept_tcp(&listener);
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(WsEvent::Error(err)) => err,
+        event => panic!("Expected the connection to fail, got: {event:?}"),
+    }
+}
+
+#[test]
+fn upgrade_timeout() {
+    let err = connect_to_silent_server(Options {
+        upgrade_timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    });
+    assert!(matches!(err, Error::Timeout(TimeoutKind::Upgrade)), "{err}");
+}
+
+#[test]
+fn open_timeout() {
+    let err = connect_to_silent_server(Options {
+        open_timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    });
+    assert!(matches!(err, Error::Timeout(TimeoutKind::Open)), "{err}");
+}
+
+#[test]
+fn the_earlier_timeout_wins() {
+    let err = connect_to_silent_server(Options {
+        upgrade_timeout: Some(Duration::from_secs(60)),
+        open_timeout: Some(Duration::from_millis(100)),
+        ..Default::default()
+    });
+    assert!(matches!(err, Error::Timeout(TimeoutKind::Open)), "{err}");
+}
+
+#[test]
+fn idle_timeout() {
+    let (url, server) = common::serve_one(|mut socket| {
+        socket.send(tungstenite::Message::text("hello")).unwrap();
+        // Then stay silent until the client hangs up:
+        while socket.read().is_ok() {}
+    });
+
+    let options = Options {
+        idle_timeout: Some(Duration::from_millis(200)),
+        ..Default::default()
+    };
+    let (_sender, receiver) = ewebsock::connect_with_options(url, options).unwrap();
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Opened(_))
+    ));
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Message(_))
+    ));
+    match receiver.recv_timeout(TIMEOUT) {
+        Ok(WsEvent::Error(Error::Timeout(TimeoutKind::Idle))) => {}
+        event => panic!("Expected an idle timeout, got: {event:?}"),
+    }
+    assert!(matches!(
+        receiver.recv_timeout(TIMEOUT),
+        Ok(WsEvent::Closed(_))
+    ));
+    server.join().unwrap();
+}
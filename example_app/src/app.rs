@@ -1,5 +1,27 @@
 use eframe::egui;
-use ewebsock::{WsEvent, WsMessage, WsReceiver, WsSender};
+use ewebsock::{WsEvent, WsMessage, WsReceiver};
+
+#[cfg(not(all(feature = "tokio", not(target_arch = "wasm32"))))]
+use ewebsock::{connect_with_wakeup, WsSender};
+
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+use ewebsock::tokio::WsSender;
+
+/// Like [`ewebsock::connect_with_wakeup`], but with the tokio backend.
+#[cfg(all(feature = "tokio", not(target_arch = "wasm32")))]
+fn connect_with_wakeup(
+    url: &str,
+    wake_up: impl Fn() + Send + Sync + 'static,
+) -> ewebsock::Result<(WsSender, WsReceiver)> {
+    let (receiver, on_event) = WsReceiver::new_with_callback(wake_up);
+    let sender = ewebsock::tokio::ws_connect(
+        url.to_owned(),
+        Default::default(),
+        ewebsock::tokio::TokioRuntime::Current,
+        on_event,
+    )?;
+    Ok((sender, receiver))
+}
 
 pub struct ExampleApp {
     url: String,
@@ -61,14 +83,14 @@ impl eframe::App for ExampleApp {
 impl ExampleApp {
     fn connect(&mut self, ctx: egui::Context) {
         let wakeup = move || ctx.request_repaint(); // wake up UI thread on new message
-        match ewebsock::connect_with_wakeup(&self.url, wakeup) {
+        match connect_with_wakeup(&self.url, wakeup) {
             Ok((ws_sender, ws_receiver)) => {
                 self.frontend = Some(FrontEnd::new(ws_sender, ws_receiver));
                 self.error.clear();
             }
             Err(error) => {
                 log::error!("Failed to connect to {:?}: {}", &self.url, error);
-                self.error = error;
+                self.error = error.to_string();
             }
         }
     }